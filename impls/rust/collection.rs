@@ -0,0 +1,839 @@
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    mem,
+    rc::Rc,
+};
+
+use indexmap::IndexMap;
+
+use crate::apply_closure;
+use crate::core::{apply, filter, lazy_cons, transducer};
+use crate::env::Env;
+use crate::types::{
+    error, seq_iter, seq_step, seq_to_vec, Closure, Function, Hashable, LazySeq, MalType, Xform,
+};
+
+pub fn ns() -> HashMap<&'static str, Function> {
+    let mut ns: HashMap<&'static str, Function> = HashMap::new();
+    ns.insert("reduce", |args| {
+        let mut items = seq_iter(args.last().unwrap());
+        let mut acc = match args.len() {
+            2 => match items.next() {
+                Some(first) => first?,
+                None => return args[0].apply(&vec![]),
+            },
+            _ => args[1].clone(),
+        };
+        for item in items {
+            match args[0].apply(&vec![acc, item?])? {
+                MalType::Reduced(value) => return Ok(*value),
+                value => acc = value,
+            }
+        }
+        Ok(acc)
+    });
+    ns.insert("remove", |args| match args.get(1) {
+        None => Ok(transducer(Xform::Filter(args[0].clone(), false))),
+        Some(coll) => Ok(filter(args[0].clone(), coll.clone(), false)),
+    });
+    ns.insert("reduced", |args| {
+        Ok(MalType::Reduced(Box::new(args[0].clone())))
+    });
+    ns.insert("reduced?", |args| {
+        Ok(if let MalType::Reduced(_) = &args[0] {
+            MalType::True
+        } else {
+            MalType::False
+        })
+    });
+    ns.insert("transducer?", |args| {
+        Ok(if let MalType::Transducer(_) = &args[0] {
+            MalType::True
+        } else {
+            MalType::False
+        })
+    });
+    ns.insert("transducer", |args| {
+        Ok(transducer(Xform::Custom(
+            args[0].clone(),
+            args.get(1).cloned(),
+        )))
+    });
+    ns.insert("comp", |args| {
+        if let Some(arg) = args.iter().find(|arg| {
+            !matches!(
+                arg,
+                MalType::Function(_, _, _) | MalType::Closure(_, _) | MalType::Transducer(_)
+            )
+        }) {
+            return error(format!("Expected function or transducer, but got {}", arg));
+        }
+        let transducers = args
+            .iter()
+            .filter(|arg| matches!(arg, MalType::Transducer(_)))
+            .count();
+        match transducers {
+            0 if !args.is_empty() => Ok(comp_functions(args)),
+            count if count == args.len() => {
+                let mut xforms = Vec::new();
+                for arg in args {
+                    if let MalType::Transducer(stages) = arg {
+                        xforms.extend(stages.iter().cloned());
+                    }
+                }
+                Ok(MalType::Transducer(Rc::new(xforms)))
+            }
+            _ => error(format!(
+                "Expected only functions or only transducers, but got {}",
+                MalType::List(args.clone(), None)
+            )),
+        }
+    });
+    ns.insert("transduce", |args| {
+        let xforms = xforms_arg(&args[0])?;
+        let f = &args[1];
+        let (init, coll) = match args.len() {
+            3 => (f.apply(&vec![])?, &args[2]),
+            _ => (args[2].clone(), &args[3]),
+        };
+        transduce(
+            &xforms,
+            &|acc, value| f.apply(&vec![acc, value]),
+            init,
+            coll,
+        )
+    });
+    ns.insert("some", |args| {
+        for item in seq_iter(&args[1]) {
+            let value = args[0].apply(&vec![item?])?;
+            if is_truthy(&value) {
+                return Ok(value);
+            }
+        }
+        Ok(MalType::Nil)
+    });
+    ns.insert("every?", |args| {
+        for item in seq_iter(&args[1]) {
+            if !is_truthy(&args[0].apply(&vec![item?])?) {
+                return Ok(MalType::False);
+            }
+        }
+        Ok(MalType::True)
+    });
+    ns.insert("sort", |args| {
+        let (comparator, coll) = match args.len() {
+            1 => (None, &args[0]),
+            _ => (Some(&args[0]), &args[1]),
+        };
+        let list = sort(seq_to_vec(coll)?, &|a, b| match comparator {
+            Some(f) => apply_comparator(f, a, b),
+            None => compare(a, b),
+        })?;
+        Ok(MalType::List(list, None))
+    });
+    ns.insert("sort-by", |args| {
+        let (keyfn, comparator, coll) = match args.len() {
+            2 => (&args[0], None, &args[1]),
+            _ => (&args[0], Some(&args[1]), &args[2]),
+        };
+        let keyed = seq_to_vec(coll)?
+            .into_iter()
+            .map(|v| Ok(MalType::List(vec![keyfn.apply(&vec![v.clone()])?, v], None)))
+            .collect::<Result<Vec<MalType>, MalType>>()?;
+        let sorted = sort(keyed, &|a, b| {
+            let (a, b) = (&a.as_list()?[0], &b.as_list()?[0]);
+            match comparator {
+                Some(f) => apply_comparator(f, a, b),
+                None => compare(a, b),
+            }
+        })?;
+        Ok(MalType::List(
+            sorted
+                .iter()
+                .map(|v| Ok(v.as_list()?[1].clone()))
+                .collect::<Result<Vec<MalType>, MalType>>()?,
+            None,
+        ))
+    });
+    ns.insert("group-by", |args| {
+        let mut map: IndexMap<Hashable, MalType> = IndexMap::new();
+        for value in seq_to_vec(&args[1])? {
+            let key = Hashable::new(&args[0].apply(&vec![value.clone()])?)?;
+            match map
+                .entry(key)
+                .or_insert_with(|| MalType::Vector(vec![], None))
+            {
+                MalType::Vector(group, _) => group.push(value),
+                _ => unreachable!(),
+            }
+        }
+        Ok(MalType::Hashmap(map, None))
+    });
+    ns.insert("frequencies", |args| {
+        let mut map: IndexMap<Hashable, MalType> = IndexMap::new();
+        for value in seq_to_vec(&args[0])? {
+            match map
+                .entry(Hashable::new(&value)?)
+                .or_insert(MalType::Number(0))
+            {
+                MalType::Number(count) => *count += 1,
+                _ => unreachable!(),
+            }
+        }
+        Ok(MalType::Hashmap(map, None))
+    });
+    ns.insert("partition", |args| {
+        let number = |value: &MalType| match value {
+            MalType::Number(n) if *n > 0 => Ok(*n as usize),
+            _ => error(format!("Expected positive number, but got {}", value)),
+        };
+        let n = number(&args[0])?;
+        let (step, pad, coll) = match args.len() {
+            2 => (n, None, &args[1]),
+            3 => (number(&args[1])?, None, &args[2]),
+            _ => (number(&args[1])?, Some(seq_to_vec(&args[2])?), &args[3]),
+        };
+        Ok(partition(n, step, pad, coll.clone()))
+    });
+    ns.insert("interleave", |args| Ok(interleave(args.clone())));
+    ns.insert("distinct", |args| {
+        let mut seen = HashSet::new();
+        let mut seen_unhashable: Vec<MalType> = Vec::new();
+        let mut result = Vec::new();
+        for value in seq_iter(&args[0]) {
+            let value = value?;
+            let new = match Hashable::new(&value) {
+                Ok(key) => seen.insert(key),
+                Err(_) if seen_unhashable.contains(&value) => false,
+                Err(_) => {
+                    seen_unhashable.push(value.clone());
+                    true
+                }
+            };
+            if new {
+                result.push(value);
+            }
+        }
+        Ok(MalType::List(result, None))
+    });
+    ns.insert("zipmap", |args| {
+        let keys = seq_to_vec(&args[0])?;
+        let values = seq_to_vec(&args[1])?;
+        let mut map = IndexMap::new();
+        for (key, value) in keys.iter().zip(values) {
+            map.insert(Hashable::new(key)?, value);
+        }
+        Ok(MalType::Hashmap(map, None))
+    });
+    ns.insert("into", |args| match args.len() {
+        2 => into(&args[0], seq_iter(&args[1])),
+        _ => {
+            let items = RefCell::new(Vec::new());
+            transduce(
+                &xforms_arg(&args[1])?,
+                &|acc, value| {
+                    items.borrow_mut().push(value);
+                    Ok(acc)
+                },
+                MalType::Nil,
+                &args[2],
+            )?;
+            into(&args[0], items.into_inner().into_iter().map(Ok))
+        }
+    });
+    ns.insert("merge", |args| {
+        let mut result = MalType::Nil;
+        for arg in args {
+            result = match (result, arg) {
+                (result, MalType::Nil) => result,
+                (MalType::Nil, MalType::Hashmap(_, _)) => arg.clone(),
+                (MalType::Hashmap(mut map, metadata), MalType::Hashmap(other, _)) => {
+                    map.extend(other.clone());
+                    MalType::Hashmap(map, metadata)
+                }
+                (_, arg) => return error(format!("Expected hashmap, but got {}", arg)),
+            };
+        }
+        Ok(result)
+    });
+    ns.insert("update", |args| {
+        if args.len() < 3 {
+            return error(format!("Expected at least 3 args, but got {}", args.len()));
+        }
+        let value = get(&args[0], &args[1])?;
+        let mut f_args = vec![value];
+        f_args.extend(args[3..].to_vec());
+        assoc(&args[0], &args[1], args[2].apply(&f_args)?)
+    });
+    ns.insert("get-in", |args| {
+        let mut value = args[0].clone();
+        for key in seq_to_vec(&args[1])? {
+            value = get(&value, &key)?;
+            if value == MalType::Nil {
+                return Ok(args.get(2).cloned().unwrap_or(MalType::Nil));
+            }
+        }
+        Ok(value)
+    });
+    ns.insert("assoc-in", |args| {
+        assoc_in(&args[0], &seq_to_vec(&args[1])?, args[2].clone())
+    });
+    ns.insert("update-in", |args| {
+        if args.len() < 3 {
+            return error(format!("Expected at least 3 args, but got {}", args.len()));
+        }
+        let keys = seq_to_vec(&args[1])?;
+        let mut value = args[0].clone();
+        for key in &keys {
+            value = get(&value, key)?;
+        }
+        let mut f_args = vec![value];
+        f_args.extend(args[3..].to_vec());
+        assoc_in(&args[0], &keys, args[2].apply(&f_args)?)
+    });
+    ns
+}
+
+/// Arglists and docstrings of the builtins in `ns()`.
+pub fn docs() -> Vec<(&'static str, &'static str, &'static str)> {
+    vec![
+        (
+            "reduce",
+            "([f coll] [f init coll])",
+            "Folds coll from the left with f, stopping early at a reduced value.",
+        ),
+        (
+            "remove",
+            "([pred] [pred coll])",
+            "Returns the items of coll for which pred is false, or a transducer.",
+        ),
+        ("reduced", "([x])", "Wraps x to stop a reduction."),
+        (
+            "reduced?",
+            "([x])",
+            "Returns true if x was returned by reduced.",
+        ),
+        ("transducer?", "([x])", "Returns true if x is a transducer."),
+        (
+            "comp",
+            "([& fs] [& xforms])",
+            "Composes functions, calling the last one first, or transducers, applying the first one first.",
+        ),
+        (
+            "transducer",
+            "([step] [step complete])",
+            "Returns a transducer calling step on each item for the items to pass on, wrapped in reduced to stop after them, and complete for the items to flush at the end.",
+        ),
+        (
+            "transduce",
+            "([xform f coll] [xform f init coll])",
+            "Reduces coll with f after transforming its items with xform.",
+        ),
+        (
+            "some",
+            "([pred coll])",
+            "Returns the first truthy result of pred on the items of coll, or nil.",
+        ),
+        (
+            "every?",
+            "([pred coll])",
+            "Returns true if pred is truthy for every item of coll.",
+        ),
+        (
+            "sort",
+            "([coll] [comparator coll])",
+            "Returns a sorted list of the items of coll.",
+        ),
+        (
+            "sort-by",
+            "([keyfn coll] [keyfn comparator coll])",
+            "Returns a list of the items of coll sorted by (keyfn item).",
+        ),
+        (
+            "group-by",
+            "([f coll])",
+            "Returns a map from each (f item) to a vector of the matching items of coll.",
+        ),
+        (
+            "frequencies",
+            "([coll])",
+            "Returns a map from each distinct item of coll to its number of occurrences.",
+        ),
+        (
+            "partition",
+            "([n coll] [n step coll] [n step pad coll])",
+            "Returns a lazy sequence of lists of n items of coll, step items apart.",
+        ),
+        (
+            "interleave",
+            "([& colls])",
+            "Returns a lazy sequence of the first item of each coll, then the second and so on.",
+        ),
+        (
+            "distinct",
+            "([coll])",
+            "Returns a list of the items of coll without duplicates.",
+        ),
+        (
+            "zipmap",
+            "([keys vals])",
+            "Returns a map from keys to the corresponding vals.",
+        ),
+        (
+            "into",
+            "([to from] [to xform from])",
+            "Adds the items of from to the collection to, optionally through xform.",
+        ),
+        (
+            "merge",
+            "([& maps])",
+            "Returns a map of the entries of maps, the later ones taking precedence.",
+        ),
+        (
+            "update",
+            "([m k f & args])",
+            "Returns m with the value of k replaced by (f value args).",
+        ),
+        (
+            "get-in",
+            "([m ks] [m ks not-found])",
+            "Returns the value found by looking up ks in nested maps and vectors.",
+        ),
+        (
+            "assoc-in",
+            "([m ks v])",
+            "Returns m with the value at the path ks in nested maps set to v.",
+        ),
+        (
+            "update-in",
+            "([m ks f & args])",
+            "Returns m with the value at the path ks replaced by (f value args).",
+        ),
+    ]
+}
+
+fn is_truthy(value: &MalType) -> bool {
+    !matches!(value, MalType::Nil | MalType::False)
+}
+
+/// Returns a function calling the last of `fs` with its arguments, then each
+/// of the others in turn with the result: `(f (g (apply h args)))`.
+fn comp_functions(fs: &[MalType]) -> MalType {
+    if let [f] = fs {
+        return f.clone();
+    }
+    let mut body = MalType::List(
+        vec![
+            MalType::Function(|args| apply(args), "apply", None),
+            fs[fs.len() - 1].clone(),
+            MalType::symbol("args"),
+        ],
+        None,
+    );
+    for f in fs[..fs.len() - 1].iter().rev() {
+        body = MalType::List(vec![f.clone(), body], None);
+    }
+    MalType::Closure(
+        Box::new(Closure::new(
+            vec![MalType::symbol("&"), MalType::symbol("args")],
+            body,
+            Rc::new(RefCell::new(Env::new(None))),
+            apply_closure,
+        )),
+        None,
+    )
+}
+
+fn xforms_arg(value: &MalType) -> Result<Rc<Vec<Xform>>, MalType> {
+    match value {
+        MalType::Transducer(xforms) => Ok(xforms.clone()),
+        _ => error(format!("Expected transducer, but got {}", value)),
+    }
+}
+
+/// Pushes each element of `coll` through `xforms` and folds the survivors
+/// into `init` with `rf`, in a single pass without intermediate collections.
+/// Stops early once `rf` returns a `reduced` value or a stage is done, then
+/// flushes the items custom stages held back.
+fn transduce(
+    xforms: &[Xform],
+    rf: &dyn Fn(MalType, MalType) -> Result<MalType, MalType>,
+    init: MalType,
+    coll: &MalType,
+) -> Result<MalType, MalType> {
+    let mut pipeline = Pipeline {
+        xforms,
+        rf,
+        counts: vec![0; xforms.len()],
+        acc: init,
+        is_reduced: false,
+    };
+    pipeline.push_all(0, coll)?;
+    for (index, xform) in xforms.iter().enumerate() {
+        if pipeline.is_reduced {
+            break;
+        }
+        if let Xform::Custom(_, Some(complete)) = xform {
+            pipeline.push_all(index + 1, &complete.apply(&vec![])?)?;
+        }
+    }
+    Ok(pipeline.acc)
+}
+
+/// The state of a running `transduce`: how many items each `take` or `drop`
+/// stage has seen and what has been folded so far.
+struct Pipeline<'a> {
+    xforms: &'a [Xform],
+    rf: &'a dyn Fn(MalType, MalType) -> Result<MalType, MalType>,
+    counts: Vec<i64>,
+    acc: MalType,
+    is_reduced: bool,
+}
+
+impl Pipeline<'_> {
+    /// Passes `value` through the stages from `start` on, returning whether
+    /// no more items should be pushed.
+    fn push(&mut self, start: usize, mut value: MalType) -> Result<bool, MalType> {
+        let mut is_last = false;
+        for (index, xform) in self.xforms.iter().enumerate().skip(start) {
+            match xform {
+                Xform::Map(f) => value = f.apply(&vec![value])?,
+                Xform::Filter(pred, keep) => {
+                    if is_truthy(&pred.apply(&vec![value.clone()])?) != *keep {
+                        return Ok(false);
+                    }
+                }
+                Xform::Take(n) => {
+                    self.counts[index] += 1;
+                    if self.counts[index] > *n {
+                        return Ok(true);
+                    }
+                    is_last |= self.counts[index] == *n;
+                }
+                Xform::Drop(n) => {
+                    if self.counts[index] < *n {
+                        self.counts[index] += 1;
+                        return Ok(false);
+                    }
+                }
+                Xform::TakeWhile(pred) => {
+                    if !is_truthy(&pred.apply(&vec![value.clone()])?) {
+                        return Ok(true);
+                    }
+                }
+                Xform::Custom(step, _) => {
+                    let (items, is_done) = match step.apply(&vec![value])? {
+                        MalType::Reduced(items) => (*items, true),
+                        items => (items, false),
+                    };
+                    return Ok(self.push_all(index + 1, &items)? || is_done || is_last);
+                }
+            }
+        }
+
+        match (self.rf)(mem::replace(&mut self.acc, MalType::Nil), value)? {
+            MalType::Reduced(value) => {
+                self.acc = *value;
+                self.is_reduced = true;
+                Ok(true)
+            }
+            value => {
+                self.acc = value;
+                Ok(is_last)
+            }
+        }
+    }
+
+    /// Pushes the items of `items` from stage `start` on until one is done.
+    fn push_all(&mut self, start: usize, items: &MalType) -> Result<bool, MalType> {
+        for item in seq_iter(items) {
+            if self.push(start, item?)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Orders numbers, chars, strings, keywords and symbols among themselves, and
+/// lists or vectors element by element.
+pub fn compare(a: &MalType, b: &MalType) -> Result<Ordering, MalType> {
+    match (a, b) {
+        (MalType::Number(a), MalType::Number(b)) => Ok(a.cmp(b)),
+        (MalType::Char(a), MalType::Char(b)) => Ok(a.cmp(b)),
+        (MalType::String(a), MalType::String(b))
+        | (MalType::Keyword(a), MalType::Keyword(b))
+        | (MalType::Symbol(a), MalType::Symbol(b)) => Ok(a.cmp(b)),
+        (
+            MalType::List(a, _) | MalType::Vector(a, _),
+            MalType::List(b, _) | MalType::Vector(b, _),
+        ) => {
+            for (a, b) in a.iter().zip(b) {
+                match compare(a, b)? {
+                    Ordering::Equal => continue,
+                    ordering => return Ok(ordering),
+                }
+            }
+            Ok(a.len().cmp(&b.len()))
+        }
+        (MalType::Nil, MalType::Nil) => Ok(Ordering::Equal),
+        (MalType::Nil, _) => Ok(Ordering::Less),
+        (_, MalType::Nil) => Ok(Ordering::Greater),
+        _ => error(format!("Cannot compare {} and {}", a, b)),
+    }
+}
+
+/// Calls a user comparator, which returns either a number whose sign gives
+/// the order or a boolean meaning "a comes before b".
+fn apply_comparator(f: &MalType, a: &MalType, b: &MalType) -> Result<Ordering, MalType> {
+    match f.apply(&vec![a.clone(), b.clone()])? {
+        MalType::Number(n) => Ok(n.cmp(&0)),
+        MalType::True => Ok(Ordering::Less),
+        MalType::False | MalType::Nil => match f.apply(&vec![b.clone(), a.clone()])? {
+            MalType::Nil | MalType::False => Ok(Ordering::Equal),
+            _ => Ok(Ordering::Greater),
+        },
+        value => error(format!(
+            "Expected number or boolean from comparator, but got {}",
+            value
+        )),
+    }
+}
+
+/// A stable merge sort that stops at the first comparator error.
+fn sort(
+    mut list: Vec<MalType>,
+    cmp: &dyn Fn(&MalType, &MalType) -> Result<Ordering, MalType>,
+) -> Result<Vec<MalType>, MalType> {
+    if list.len() <= 1 {
+        return Ok(list);
+    }
+
+    let right = sort(list.split_off(list.len() / 2), cmp)?;
+    let left = sort(list, cmp)?;
+    let mut result = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if cmp(b, a)? == Ordering::Less {
+            result.push(right.next().unwrap());
+        } else {
+            result.push(left.next().unwrap());
+        }
+    }
+    result.extend(left);
+    result.extend(right);
+    Ok(result)
+}
+
+fn partition(n: usize, step: usize, pad: Option<Vec<MalType>>, coll: MalType) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || {
+        let mut chunk = Vec::new();
+        let mut current = coll.clone();
+        while chunk.len() < n {
+            match seq_step(&current)? {
+                Some((first, rest)) => {
+                    chunk.push(first);
+                    current = rest;
+                }
+                None => break,
+            }
+        }
+
+        if chunk.len() < n {
+            return match &pad {
+                Some(pad) if !chunk.is_empty() => {
+                    chunk.extend(pad.iter().take(n - chunk.len()).cloned());
+                    Ok(MalType::List(vec![MalType::List(chunk, None)], None))
+                }
+                _ => Ok(MalType::Nil),
+            };
+        }
+
+        let mut rest = coll.clone();
+        for _ in 0..step {
+            match seq_step(&rest)? {
+                Some((_, next)) => rest = next,
+                None => {
+                    rest = MalType::Nil;
+                    break;
+                }
+            }
+        }
+        lazy_cons(
+            MalType::List(chunk, None),
+            partition(n, step, pad.clone(), rest),
+        )
+    }))
+}
+
+fn interleave(colls: Vec<MalType>) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || {
+        let mut firsts = Vec::new();
+        let mut rests = Vec::new();
+        for coll in &colls {
+            match seq_step(coll)? {
+                Some((first, rest)) => {
+                    firsts.push(first);
+                    rests.push(rest);
+                }
+                None => return Ok(MalType::Nil),
+            }
+        }
+        if firsts.is_empty() {
+            return Ok(MalType::Nil);
+        }
+
+        let rest = interleave(rests);
+        firsts
+            .into_iter()
+            .rev()
+            .try_fold(rest, |rest, first| lazy_cons(first, rest))
+    }))
+}
+
+fn conj(to: &MalType, value: MalType) -> Result<MalType, MalType> {
+    match to {
+        MalType::Nil => Ok(MalType::List(vec![value], None)),
+        MalType::List(list, metadata) => {
+            let mut result = vec![value];
+            result.extend(list.clone());
+            Ok(MalType::List(result, metadata.clone()))
+        }
+        MalType::Vector(list, metadata) => {
+            let mut result = list.clone();
+            result.push(value);
+            Ok(MalType::Vector(result, metadata.clone()))
+        }
+        MalType::Hashmap(map, metadata) => {
+            let mut map = map.clone();
+            insert_entry(&mut map, value)?;
+            Ok(MalType::Hashmap(map, metadata.clone()))
+        }
+        MalType::Set(set, metadata) => {
+            let mut set = set.clone();
+            set.insert(Hashable::new(&value)?);
+            Ok(MalType::Set(set, metadata.clone()))
+        }
+        MalType::LazySeq(_) => Ok(MalType::LazySeq(LazySeq::cons(value, to.clone()))),
+        _ => error(format!("Expected collection, but got {}", to)),
+    }
+}
+
+/// Adds `value`, a map or a `[key value]` entry, to `map`.
+fn insert_entry(map: &mut IndexMap<Hashable, MalType>, value: MalType) -> Result<(), MalType> {
+    match &value {
+        MalType::Hashmap(entries, _) => map.extend(entries.clone()),
+        MalType::List(entry, _) | MalType::Vector(entry, _) if entry.len() == 2 => {
+            map.insert(Hashable::new(&entry[0])?, entry[1].clone());
+        }
+        _ => return error(format!("Expected map entry, but got {}", value)),
+    }
+    Ok(())
+}
+
+/// Adds every item to `to` like repeated `conj`, but copying `to` only once.
+fn into(
+    to: &MalType,
+    mut items: impl Iterator<Item = Result<MalType, MalType>>,
+) -> Result<MalType, MalType> {
+    match to {
+        MalType::Nil | MalType::List(_, _) => {
+            let mut result = items.collect::<Result<Vec<MalType>, MalType>>()?;
+            result.reverse();
+            let metadata = match to {
+                MalType::List(list, metadata) => {
+                    result.extend(list.iter().cloned());
+                    metadata.clone()
+                }
+                _ => None,
+            };
+            Ok(MalType::List(result, metadata))
+        }
+        MalType::Vector(list, metadata) => {
+            let mut result = list.clone();
+            for item in items {
+                result.push(item?);
+            }
+            Ok(MalType::Vector(result, metadata.clone()))
+        }
+        MalType::Hashmap(map, metadata) => {
+            let mut map = map.clone();
+            for item in items {
+                insert_entry(&mut map, item?)?;
+            }
+            Ok(MalType::Hashmap(map, metadata.clone()))
+        }
+        MalType::Set(set, metadata) => {
+            let mut set = set.clone();
+            for item in items {
+                set.insert(Hashable::new(&item?)?);
+            }
+            Ok(MalType::Set(set, metadata.clone()))
+        }
+        _ => items.try_fold(to.clone(), |to, item| conj(&to, item?)),
+    }
+}
+
+fn get(coll: &MalType, key: &MalType) -> Result<MalType, MalType> {
+    match (coll, key) {
+        (MalType::Nil, _) => Ok(MalType::Nil),
+        (MalType::Hashmap(map, _), key) => Ok(map
+            .get(&Hashable::new(key)?)
+            .cloned()
+            .unwrap_or(MalType::Nil)),
+        (MalType::Set(set, _), key) => {
+            let key = Hashable::new(key)?;
+            Ok(if set.contains(&key) {
+                key.to_mal()
+            } else {
+                MalType::Nil
+            })
+        }
+        (MalType::Vector(list, _), MalType::Number(index)) => Ok(usize::try_from(*index)
+            .ok()
+            .and_then(|index| list.get(index))
+            .cloned()
+            .unwrap_or(MalType::Nil)),
+        _ => error(format!("Expected hashmap or vector, but got {}", coll)),
+    }
+}
+
+fn assoc(coll: &MalType, key: &MalType, value: MalType) -> Result<MalType, MalType> {
+    match (coll, key) {
+        (MalType::Nil, key) => Ok(MalType::Hashmap(
+            IndexMap::from([(Hashable::new(key)?, value)]),
+            None,
+        )),
+        (MalType::Hashmap(map, metadata), key) => {
+            let mut map = map.clone();
+            map.insert(Hashable::new(key)?, value);
+            Ok(MalType::Hashmap(map, metadata.clone()))
+        }
+        (MalType::Vector(list, metadata), MalType::Number(index))
+            if *index >= 0 && *index as usize <= list.len() =>
+        {
+            let mut list = list.clone();
+            if *index as usize == list.len() {
+                list.push(value);
+            } else {
+                list[*index as usize] = value;
+            }
+            Ok(MalType::Vector(list, metadata.clone()))
+        }
+        _ => error(format!("Cannot associate {} in {}", key, coll)),
+    }
+}
+
+fn assoc_in(coll: &MalType, keys: &[MalType], value: MalType) -> Result<MalType, MalType> {
+    match keys {
+        [] => Ok(value),
+        [key] => assoc(coll, key, value),
+        [key, rest @ ..] => {
+            let inner = assoc_in(&get(coll, key)?, rest, value)?;
+            assoc(coll, key, inner)
+        }
+    }
+}
@@ -3,12 +3,17 @@ use crate::Rc;
 use crate::RefCell;
 use std::collections::HashMap;
 use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
-use crate::printer::pr_str;
-use crate::reader::read_str;
-use crate::types::{error, Function, MalType};
+use crate::printer::{err_port, in_port, out_port, pr_str, print_str, realize_printed};
+use crate::reader::{read_all, read_edn, read_str};
+use crate::types::{
+    equals, error, map_entries, realize, seq_iter, seq_step, seq_to_vec, Function, LazySeq,
+    MalType, Port, Xform,
+};
+use indexmap::{IndexMap, IndexSet};
 use rustyline::Editor;
 
 pub fn ns() -> HashMap<&'static str, Function> {
@@ -18,19 +23,57 @@ pub fn ns() -> HashMap<&'static str, Function> {
     ns.insert("*", |args| binary_number_op(args, |a, b| a * b));
     ns.insert("/", |args| binary_number_op(args, |a, b| a / b));
     ns.insert("prn", |args| {
-        if args.is_empty() {
-            println!();
-        } else {
-            println!("{}", join(args, true, " "));
-        };
+        out_port().write(&format!("{}\n", print_join(args, true, " ")?))?;
         Ok(MalType::Nil)
     });
-    ns.insert("pr-str", |args| Ok(MalType::String(join(args, true, " "))));
-    ns.insert("str", |args| Ok(MalType::String(join(args, false, ""))));
+    ns.insert("pr-str", |args| {
+        Ok(MalType::String(print_join(args, true, " ")?))
+    });
+    ns.insert("str", |args| Ok(MalType::String(join(args, false, "")?)));
     ns.insert("println", |args| {
-        println!("{}", join(args, false, " "));
+        out_port().write(&format!("{}\n", print_join(args, false, " ")?))?;
+        Ok(MalType::Nil)
+    });
+    ns.insert("print", |args| {
+        out_port().write(&print_join(args, false, " ")?)?;
+        Ok(MalType::Nil)
+    });
+    ns.insert("eprintln", |args| {
+        err_port().write(&format!("{}\n", print_join(args, false, " ")?))?;
+        Ok(MalType::Nil)
+    });
+    ns.insert("flush", |_| {
+        out_port().flush()?;
         Ok(MalType::Nil)
     });
+    ns.insert("read-line", |args| {
+        let port = match args.first() {
+            Some(value) => port_arg(value)?.clone(),
+            None => in_port(),
+        };
+        Ok(port.read_line()?.map_or(MalType::Nil, MalType::String))
+    });
+    ns.insert("string-writer", |_| {
+        Ok(MalType::Port(Port::Writer(Rc::new(RefCell::new(
+            String::new(),
+        )))))
+    });
+    ns.insert("string-reader", |args| {
+        Ok(MalType::Port(Port::Reader(Rc::new(RefCell::new(
+            args[0].as_string()?.clone(),
+        )))))
+    });
+    ns.insert("written-str", |args| match port_arg(&args[0])? {
+        Port::Writer(buffer) => Ok(MalType::String(buffer.borrow().clone())),
+        _ => error(format!("Expected string writer, but got {}", args[0])),
+    });
+    ns.insert("port?", |args| {
+        Ok(if let MalType::Port(_) = args[0] {
+            MalType::True
+        } else {
+            MalType::False
+        })
+    });
     ns.insert("list", |args| Ok(MalType::List(args.clone(), None)));
     ns.insert("list?", |args| {
         unary_op(args, |v| {
@@ -50,6 +93,35 @@ pub fn ns() -> HashMap<&'static str, Function> {
                     Ok(MalType::False)
                 }
             }
+            MalType::Set(set, _) => {
+                if set.is_empty() {
+                    Ok(MalType::True)
+                } else {
+                    Ok(MalType::False)
+                }
+            }
+            MalType::Hashmap(map, _) => {
+                if map.is_empty() {
+                    Ok(MalType::True)
+                } else {
+                    Ok(MalType::False)
+                }
+            }
+            MalType::String(value) => {
+                if value.is_empty() {
+                    Ok(MalType::True)
+                } else {
+                    Ok(MalType::False)
+                }
+            }
+            MalType::Nil => Ok(MalType::True),
+            MalType::LazySeq(seq) => {
+                if seq.step()?.is_none() {
+                    Ok(MalType::True)
+                } else {
+                    Ok(MalType::False)
+                }
+            }
             value => error(format!("Expected list but got {}.", value)),
         })
     });
@@ -58,13 +130,17 @@ pub fn ns() -> HashMap<&'static str, Function> {
             MalType::List(list, _) | MalType::Vector(list, _) => {
                 Ok(MalType::Number(list.len() as i64))
             }
+            MalType::Set(set, _) => Ok(MalType::Number(set.len() as i64)),
+            MalType::Hashmap(map, _) => Ok(MalType::Number(map.len() as i64)),
+            MalType::String(value) => Ok(MalType::Number(value.chars().count() as i64)),
             MalType::Nil => Ok(MalType::Number(0)),
+            MalType::LazySeq(_) => Ok(MalType::Number(seq_to_vec(v)?.len() as i64)),
             value => error(format!("Expected list or nil but got {}.", value)),
         })
     });
     ns.insert("=", |args| {
         binary_op(args, |a, b| {
-            if a == b {
+            if equals(a, b)? {
                 Ok(MalType::True)
             } else {
                 Ok(MalType::False)
@@ -78,6 +154,41 @@ pub fn ns() -> HashMap<&'static str, Function> {
     ns.insert("read-string", |args| {
         args[0].as_string().and_then(|v| read_str(v))
     });
+    ns.insert("edn-read-string", |args| {
+        let (options, string) = match args.len() {
+            1 => (None, &args[0]),
+            _ => (Some(&args[0]), &args[1]),
+        };
+        let (readers, default_reader) = match options {
+            None | Some(MalType::Nil) => (IndexMap::new(), None),
+            Some(MalType::Hashmap(options, _)) => (
+                match options.get(&Hashable::Keyword("readers".to_string())) {
+                    None | Some(MalType::Nil) => IndexMap::new(),
+                    Some(MalType::Hashmap(readers, _)) => readers.clone(),
+                    Some(value) => return error(format!("Expected hashmap, but got {}", value)),
+                },
+                options
+                    .get(&Hashable::Keyword("default".to_string()))
+                    .filter(|reader| **reader != MalType::Nil)
+                    .cloned(),
+            ),
+            Some(value) => return error(format!("Expected hashmap, but got {}", value)),
+        };
+        read_edn(string.as_string()?, readers, default_reader)
+    });
+    ns.insert("read-string-all", |args| {
+        args[0]
+            .as_string()
+            .and_then(|v| read_all(v))
+            .map(|forms| MalType::List(forms, None))
+    });
+    ns.insert("read-all", |args| {
+        args[0]
+            .as_string()
+            .and_then(|v| read_file(v))
+            .and_then(|v| read_all(v.as_string()?))
+            .map(|forms| MalType::List(forms, None))
+    });
     ns.insert("slurp", |args| {
         args[0].as_string().and_then(|v| read_file(v))
     });
@@ -117,7 +228,7 @@ pub fn ns() -> HashMap<&'static str, Function> {
         }
 
         let result = match &args[1] {
-            MalType::Function(f, _) => f(&f_args),
+            MalType::Function(f, _, _) => f(&f_args),
             MalType::Closure(closure, _) => closure.apply(&f_args),
             _ => return error(format!("Expected function, but got {}", &args[1])),
         };
@@ -132,6 +243,12 @@ pub fn ns() -> HashMap<&'static str, Function> {
         let head = &args[0];
         let tail = match &args[1] {
             MalType::List(list, _) | MalType::Vector(list, _) => list,
+            MalType::LazySeq(_) => {
+                return Ok(MalType::LazySeq(LazySeq::cons(
+                    head.clone(),
+                    args[1].clone(),
+                )))
+            }
             _ => return error(format!("Expected list or vector, but got {}", &args[1])),
         };
 
@@ -146,6 +263,7 @@ pub fn ns() -> HashMap<&'static str, Function> {
         for arg in args {
             match arg {
                 MalType::List(list, _) | MalType::Vector(list, _) => result.extend(list.clone()),
+                MalType::LazySeq(_) => result.extend(seq_to_vec(arg)?),
                 _ => return error(format!("Expected list or vector, but got {}", arg)),
             };
         }
@@ -155,11 +273,17 @@ pub fn ns() -> HashMap<&'static str, Function> {
     ns.insert("vec", |args| match &args[0] {
         MalType::List(list, _) => Ok(MalType::Vector(list.clone(), None)),
         MalType::Vector(_, _) => Ok(args[0].clone()),
+        MalType::LazySeq(_) | MalType::Set(_, _) => {
+            Ok(MalType::Vector(seq_to_vec(&args[0])?, None))
+        }
         _ => error(format!("Expected list or vector, but got {}", &args[0])),
     });
     ns.insert("nth", |args| {
         let index = match &args[1] {
-            MalType::Number(value) => *value as usize,
+            MalType::Number(value) if *value >= 0 => *value as usize,
+            MalType::Number(value) => {
+                return error(format!("Expected non-negative index, but got {}", value))
+            }
             _ => return error(format!("Expected number, but got {}", &args[1])),
         };
         match &args[0] {
@@ -173,6 +297,18 @@ pub fn ns() -> HashMap<&'static str, Function> {
             MalType::List(list, _) | MalType::Vector(list, _) => {
                 Ok(list.get(index).unwrap().clone())
             }
+            MalType::LazySeq(_) => {
+                for (position, item) in seq_iter(&args[0]).enumerate() {
+                    let item = item?;
+                    if position == index {
+                        return Ok(item);
+                    }
+                }
+                error(format!(
+                    "Out of range: The index was {} but the sequence is shorter",
+                    index
+                ))
+            }
             _ => error(format!("Expected list or vector, but got {}", &args[0])),
         }
     });
@@ -180,6 +316,9 @@ pub fn ns() -> HashMap<&'static str, Function> {
         MalType::Nil => Ok(MalType::Nil),
         MalType::List(list, _) | MalType::Vector(list, _) if list.is_empty() => Ok(MalType::Nil),
         MalType::List(list, _) | MalType::Vector(list, _) => Ok(list.get(0).unwrap().clone()),
+        MalType::String(_) | MalType::Hashmap(_, _) | MalType::Set(_, _) | MalType::LazySeq(_) => {
+            Ok(seq_step(&args[0])?.map_or(MalType::Nil, |(first, _)| first))
+        }
         _ => error(format!("Expected list or vector, but got {}", &args[0])),
     });
     ns.insert("rest", |args| match &args[0] {
@@ -190,34 +329,34 @@ pub fn ns() -> HashMap<&'static str, Function> {
         MalType::List(list, _) | MalType::Vector(list, _) => {
             Ok(MalType::List(list[1..].to_vec(), None))
         }
+        MalType::String(value) => Ok(MalType::List(
+            value.chars().skip(1).map(MalType::Char).collect(),
+            None,
+        )),
+        MalType::Hashmap(_, _) | MalType::Set(_, _) => {
+            Ok(seq_step(&args[0])?.map_or(MalType::List(vec![], None), |(_, rest)| rest))
+        }
+        MalType::LazySeq(seq) => Ok(seq
+            .step()?
+            .map_or(MalType::List(vec![], None), |(_, rest)| rest)),
         _ => error(format!("Expected list or vector, but got {}", &args[0])),
     });
     ns.insert("throw", |args| Err(args[0].clone()));
-    ns.insert("apply", |args| {
-        let last_index = args.len() - 1;
-        let f_args = match &args[last_index] {
-            MalType::List(list, _) | MalType::Vector(list, _) => {
-                let mut f_args = Vec::new();
-                f_args.extend(args[1..last_index].to_vec());
-                f_args.extend(list.clone());
-                f_args
-            }
-            last_arg => return error(format!("Expected list or vector, but got {}", &last_arg)),
-        };
-        match &args[0] {
-            MalType::Closure(closure, _) => closure.apply(&f_args),
-            MalType::Function(f, _) => f(&f_args),
-            _ => error(format!("Expected function, but got {}", &args[0])),
-        }
-    });
-    ns.insert("map", |args| match &args[1] {
-        MalType::List(list, _) | MalType::Vector(list, _) => {
+    ns.insert("apply", |args| apply(args));
+    ns.insert("map", |args| match args.get(1) {
+        None => Ok(transducer(Xform::Map(args[0].clone()))),
+        Some(
+            coll @ (MalType::List(_, _)
+            | MalType::Vector(_, _)
+            | MalType::Hashmap(_, _)
+            | MalType::Set(_, _)),
+        ) => {
             let mut result = Vec::new();
-            for value in list {
+            for value in seq_to_vec(coll)? {
                 let f_args = vec![value.clone()];
                 let value = match &args[0] {
                     MalType::Closure(closure, _) => closure.apply(&f_args),
-                    MalType::Function(f, _) => f(&f_args),
+                    MalType::Function(f, _, _) => f(&f_args),
                     _ => return error(format!("Expected function, but got {}", &args[0])),
                 };
                 match value {
@@ -227,7 +366,8 @@ pub fn ns() -> HashMap<&'static str, Function> {
             }
             Ok(MalType::List(result, None))
         }
-        _ => error(format!("Expected list or vector, but got {}", &args[0])),
+        Some(MalType::LazySeq(_)) => Ok(lazy_map(args[0].clone(), args[1].clone())),
+        _ => error(format!("Expected list or vector, but got {}", &args[1])),
     });
     ns.insert("nil?", |args| {
         Ok(if let MalType::Nil = &args[0] {
@@ -286,7 +426,7 @@ pub fn ns() -> HashMap<&'static str, Function> {
     });
     ns.insert("sequential?", |args| {
         Ok(match &args[0] {
-            MalType::List(_, _) | MalType::Vector(_, _) => MalType::True,
+            MalType::List(_, _) | MalType::Vector(_, _) | MalType::LazySeq(_) => MalType::True,
             _ => MalType::False,
         })
     });
@@ -299,18 +439,46 @@ pub fn ns() -> HashMap<&'static str, Function> {
             ));
         }
 
-        let mut map = HashMap::new();
+        let mut map = IndexMap::new();
         for i in (0..count).step_by(2) {
-            let key = match &args[i] {
-                MalType::String(value) => Hashable::String(value.clone()),
-                MalType::Keyword(value) => Hashable::Keyword(value.clone()),
-                _ => return error(format!("Expected string or keyword, but got {}", &args[i])),
-            };
+            let key = Hashable::new(&args[i])?;
             let value = &args[i + 1];
             map.insert(key, value.clone());
         }
         Ok(MalType::Hashmap(map, None))
     });
+    ns.insert("hash-set", |args| {
+        let mut set = IndexSet::new();
+        for arg in args {
+            set.insert(Hashable::new(arg)?);
+        }
+        Ok(MalType::Set(set, None))
+    });
+    ns.insert("set", |args| {
+        let mut set = IndexSet::new();
+        for item in seq_iter(&args[0]) {
+            set.insert(Hashable::new(&item?)?);
+        }
+        Ok(MalType::Set(set, None))
+    });
+    ns.insert("set?", |args| {
+        Ok(if let MalType::Set(_, _) = &args[0] {
+            MalType::True
+        } else {
+            MalType::False
+        })
+    });
+    ns.insert("disj", |args| {
+        let (mut set, metadata) = match &args[0] {
+            MalType::Set(set, metadata) => (set.clone(), metadata.clone()),
+            MalType::Nil => return Ok(MalType::Nil),
+            _ => return error(format!("Expected set, but got {}", &args[0])),
+        };
+        for item in &args[1..] {
+            set.shift_remove(&Hashable::new(item)?);
+        }
+        Ok(MalType::Set(set, metadata))
+    });
     ns.insert("map?", |args| {
         Ok(if let MalType::Hashmap(_, _) = &args[0] {
             MalType::True
@@ -332,11 +500,7 @@ pub fn ns() -> HashMap<&'static str, Function> {
             _ => return error(format!("Expected hashmap, but got {}", &args[0])),
         };
         for i in (1..count).step_by(2) {
-            let key = match &args[i] {
-                MalType::String(value) => Hashable::String(value.clone()),
-                MalType::Keyword(value) => Hashable::Keyword(value.clone()),
-                _ => return error(format!("Expected string or keyword, but got {}", &args[i])),
-            };
+            let key = Hashable::new(&args[i])?;
             let value = &args[i + 1];
             map.insert(key, value.clone());
         }
@@ -348,26 +512,25 @@ pub fn ns() -> HashMap<&'static str, Function> {
             _ => return error(format!("Expected hashmap, but got {}", &args[0])),
         };
         for key in &args[1..] {
-            let key = match key {
-                MalType::String(value) => Hashable::String(value.clone()),
-                MalType::Keyword(value) => Hashable::Keyword(value.clone()),
-                _ => return error(format!("Expected string or keyword, but got {}", &key)),
-            };
-            map.remove(&key);
+            map.shift_remove(&Hashable::new(key)?);
         }
         Ok(MalType::Hashmap(map, None))
     });
     ns.insert("get", |args| {
         let map = match &args[0] {
             MalType::Hashmap(map, _) => map.clone(),
+            MalType::Set(set, _) => {
+                let key = Hashable::new(&args[1])?;
+                return Ok(if set.contains(&key) {
+                    key.to_mal()
+                } else {
+                    MalType::Nil
+                });
+            }
             MalType::Nil => return Ok(MalType::Nil),
             _ => return error(format!("Expected hashmap, but got {}", &args[0])),
         };
-        let key = match &args[1] {
-            MalType::String(value) => Hashable::String(value.clone()),
-            MalType::Keyword(value) => Hashable::Keyword(value.clone()),
-            _ => return error(format!("Expected string or keyword, but got {}", &args[1])),
-        };
+        let key = Hashable::new(&args[1])?;
         match map.get(&key) {
             Some(value) => Ok(value.clone()),
             None => Ok(MalType::Nil),
@@ -376,13 +539,16 @@ pub fn ns() -> HashMap<&'static str, Function> {
     ns.insert("contains?", |args| {
         let map = match &args[0] {
             MalType::Hashmap(map, _) => map.clone(),
+            MalType::Set(set, _) => {
+                return Ok(if set.contains(&Hashable::new(&args[1])?) {
+                    MalType::True
+                } else {
+                    MalType::False
+                })
+            }
             _ => return error(format!("Expected hashmap, but got {}", &args[0])),
         };
-        let key = match &args[1] {
-            MalType::String(value) => Hashable::String(value.clone()),
-            MalType::Keyword(value) => Hashable::Keyword(value.clone()),
-            _ => return error(format!("Expected string or keyword, but got {}", &args[1])),
-        };
+        let key = Hashable::new(&args[1])?;
         match map.get(&key) {
             Some(_) => Ok(MalType::True),
             None => Ok(MalType::False),
@@ -396,10 +562,7 @@ pub fn ns() -> HashMap<&'static str, Function> {
 
         let mut keys = Vec::new();
         for key in map.keys() {
-            keys.push(match key {
-                Hashable::String(value) => MalType::String(value.clone()),
-                Hashable::Keyword(value) => MalType::Keyword(value.clone()),
-            });
+            keys.push(key.to_mal());
         }
 
         Ok(MalType::List(keys, None))
@@ -427,32 +590,9 @@ pub fn ns() -> HashMap<&'static str, Function> {
             Err(_) => Ok(MalType::Nil),
         }
     });
-    ns.insert("meta", |args| match &args[0] {
-        MalType::List(_, metadata)
-        | MalType::Vector(_, metadata)
-        | MalType::Hashmap(_, metadata)
-        | MalType::Function(_, metadata)
-        | MalType::Closure(_, metadata) => Ok(metadata
-            .as_ref()
-            .map_or(MalType::Nil, |v| v.as_ref().clone())),
-        _ => error(format!(
-            "Expected list/vector/hashmap/function, but got {}",
-            &args[0]
-        )),
-    });
+    ns.insert("meta", |args| args[0].meta());
     ns.insert("with-meta", |args| {
-        let new_metadata = args.get(1).map(|v| Box::new(v.clone()));
-        match &args[0] {
-            MalType::List(list, _) => Ok(MalType::List(list.clone(), new_metadata)),
-            MalType::Vector(list, _) => Ok(MalType::Vector(list.clone(), new_metadata)),
-            MalType::Hashmap(map, _) => Ok(MalType::Hashmap(map.clone(), new_metadata)),
-            MalType::Function(f, _) => Ok(MalType::Function(f.clone(), new_metadata)),
-            MalType::Closure(closure, _) => Ok(MalType::Closure(closure.clone(), new_metadata)),
-            _ => error(format!(
-                "Expected list/vector/hashmap/function, but got {}",
-                &args[0]
-            )),
-        }
+        args[0].with_meta(args.get(1).map(|v| Box::new(v.clone())))
     });
     ns.insert("time-ms", |_| {
         SystemTime::now().duration_since(UNIX_EPOCH).map_or_else(
@@ -477,6 +617,13 @@ pub fn ns() -> HashMap<&'static str, Function> {
             }
             Ok(MalType::Vector(result, metadata.clone()))
         }
+        MalType::Set(set, metadata) => {
+            let mut result = set.clone();
+            for v in &args[1..] {
+                result.insert(Hashable::new(v)?);
+            }
+            Ok(MalType::Set(result, metadata.clone()))
+        }
         _ => error(format!("Expected list or vector, but got {}", &args[0])),
     });
     ns.insert("string?", |args| {
@@ -486,6 +633,27 @@ pub fn ns() -> HashMap<&'static str, Function> {
             MalType::False
         })
     });
+    ns.insert("char", |args| match &args[0] {
+        MalType::Char(_) => Ok(args[0].clone()),
+        MalType::Number(value) => u32::try_from(*value)
+            .ok()
+            .and_then(char::from_u32)
+            .map(MalType::Char)
+            .ok_or_else(|| MalType::String(format!("Invalid code point {}", value))),
+        _ => error(format!("Expected number or char, but got {}", &args[0])),
+    });
+    ns.insert("char?", |args| {
+        Ok(if let MalType::Char(_) = &args[0] {
+            MalType::True
+        } else {
+            MalType::False
+        })
+    });
+    ns.insert("int", |args| match &args[0] {
+        MalType::Char(c) => Ok(MalType::Number(*c as i64)),
+        MalType::Number(_) => Ok(args[0].clone()),
+        _ => error(format!("Expected number or char, but got {}", &args[0])),
+    });
     ns.insert("number?", |args| {
         Ok(if let MalType::Number(_) = &args[0] {
             MalType::True
@@ -494,7 +662,7 @@ pub fn ns() -> HashMap<&'static str, Function> {
         })
     });
     ns.insert("fn?", |args| match &args[0] {
-        MalType::Function(_, _) => Ok(MalType::True),
+        MalType::Function(_, _, _) => Ok(MalType::True),
         MalType::Closure(closure, _) if !closure.is_macro => Ok(MalType::True),
         _ => Ok(MalType::False),
     });
@@ -502,6 +670,36 @@ pub fn ns() -> HashMap<&'static str, Function> {
         MalType::Closure(closure, _) if closure.is_macro => Ok(MalType::True),
         _ => Ok(MalType::False),
     });
+    ns.insert("gensym", |args| {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let prefix = match args.first() {
+            Some(prefix) => prefix.as_string()?.as_str(),
+            None => "G__",
+        };
+        Ok(MalType::Symbol(format!(
+            "{}{}",
+            prefix,
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        )))
+    });
+    ns.insert("fn-name", |args| match &args[0] {
+        MalType::Function(_, name, _) => Ok(MalType::String(name.to_string())),
+        MalType::Closure(closure, _) => Ok(closure
+            .name
+            .as_ref()
+            .map_or(MalType::Nil, |name| MalType::String(name.clone()))),
+        _ => error(format!("Expected function, but got {}", &args[0])),
+    });
+    ns.insert("arglists", |args| match &args[0] {
+        MalType::Function(_, name, _) => {
+            Ok(builtin_doc(name).map_or(MalType::Nil, |(arglists, _)| arglists))
+        }
+        MalType::Closure(closure, _) => Ok(MalType::List(
+            vec![MalType::Vector(closure.params.clone(), None)],
+            None,
+        )),
+        _ => error(format!("Expected function, but got {}", &args[0])),
+    });
     ns.insert("seq", |args| match &args[0] {
         MalType::List(list, _) | MalType::Vector(list, _) if list.is_empty() => Ok(MalType::Nil),
         MalType::List(list, metadata) | MalType::Vector(list, metadata) => {
@@ -509,18 +707,407 @@ pub fn ns() -> HashMap<&'static str, Function> {
         }
         MalType::String(value) if value.is_empty() => Ok(MalType::Nil),
         MalType::String(value) => Ok(MalType::List(
-            value
-                .chars()
-                .map(|c| MalType::String(String::from(c)))
-                .collect(),
+            value.chars().map(MalType::Char).collect(),
+            None,
+        )),
+        MalType::Hashmap(map, _) if map.is_empty() => Ok(MalType::Nil),
+        MalType::Hashmap(map, _) => Ok(MalType::List(map_entries(map), None)),
+        MalType::Set(set, _) if set.is_empty() => Ok(MalType::Nil),
+        MalType::Set(set, _) => Ok(MalType::List(
+            set.iter().map(Hashable::to_mal).collect(),
             None,
         )),
         MalType::Nil => Ok(MalType::Nil),
+        MalType::LazySeq(seq) => Ok(match seq.step()? {
+            Some(_) => args[0].clone(),
+            None => MalType::Nil,
+        }),
         _ => error(format!("Expected list/vector/s, but got {}", &args[0])),
     });
+    ns.insert("lazy-seq*", |args| {
+        let f = args[0].clone();
+        Ok(MalType::LazySeq(LazySeq::new(move || f.apply(&vec![]))))
+    });
+    ns.insert("doall", |args| {
+        realize(&args[0])?;
+        Ok(args[0].clone())
+    });
+    ns.insert("range", |args| {
+        let numbers = args
+            .iter()
+            .map(|v| match v {
+                MalType::Number(n) => Ok(*n),
+                v => error(format!("Expected number, but got {}", v)),
+            })
+            .collect::<Result<Vec<i64>, MalType>>()?;
+        Ok(match numbers[..] {
+            [] => range(0, None, 1),
+            [end] => range(0, Some(end), 1),
+            [start, end] => range(start, Some(end), 1),
+            [start, end, step, ..] => range(start, Some(end), step),
+        })
+    });
+    ns.insert("iterate", |args| {
+        Ok(iterate(args[0].clone(), args[1].clone()))
+    });
+    ns.insert("repeat", |args| match args.len() {
+        1 => Ok(repeat(None, args[0].clone())),
+        _ => match &args[0] {
+            MalType::Number(n) => Ok(repeat(Some(*n), args[1].clone())),
+            v => error(format!("Expected number, but got {}", v)),
+        },
+    });
+    ns.insert("cycle", |args| {
+        let coll = args[0].clone();
+        Ok(MalType::LazySeq(LazySeq::new(move || {
+            Ok(cycle(seq_to_vec(&coll)?, 0))
+        })))
+    });
+    ns.insert("take", |args| match (&args[0], args.get(1)) {
+        (MalType::Number(n), None) => Ok(transducer(Xform::Take(*n))),
+        (MalType::Number(n), Some(coll)) => Ok(take(*n, coll.clone())),
+        (v, _) => error(format!("Expected number, but got {}", v)),
+    });
+    ns.insert("drop", |args| match (&args[0], args.get(1)) {
+        (MalType::Number(n), None) => Ok(transducer(Xform::Drop(*n))),
+        (MalType::Number(n), Some(coll)) => Ok(drop(*n, coll.clone())),
+        (v, _) => error(format!("Expected number, but got {}", v)),
+    });
+    ns.insert("take-while", |args| match args.get(1) {
+        None => Ok(transducer(Xform::TakeWhile(args[0].clone()))),
+        Some(coll) => Ok(take_while(args[0].clone(), coll.clone())),
+    });
+    ns.insert("filter", |args| match args.get(1) {
+        None => Ok(transducer(Xform::Filter(args[0].clone(), true))),
+        Some(coll) => Ok(filter(args[0].clone(), coll.clone(), true)),
+    });
     ns
 }
 
+thread_local! {
+    static DOCS: RefCell<HashMap<&'static str, (&'static str, &'static str)>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Makes the arglists and docstrings of builtins available to `arglists` and
+/// `doc`.
+pub fn register_docs(docs: impl Iterator<Item = (&'static str, &'static str, &'static str)>) {
+    DOCS.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        for (name, arglists, doc) in docs {
+            registry.insert(name, (arglists, doc));
+        }
+    });
+}
+
+/// Returns the arglists and docstring registered for the builtin `name`.
+pub fn builtin_doc(name: &str) -> Option<(MalType, String)> {
+    DOCS.with(|registry| {
+        registry
+            .borrow()
+            .get(name)
+            .map(|(arglists, doc)| (read_str(arglists).unwrap_or(MalType::Nil), doc.to_string()))
+    })
+}
+
+/// Arglists and docstrings of the builtins in `ns()`.
+pub fn docs() -> Vec<(&'static str, &'static str, &'static str)> {
+    vec![
+        ("+", "([a b])", "Returns the sum of a and b."),
+        ("-", "([a b])", "Returns a minus b."),
+        ("*", "([a b])", "Returns the product of a and b."),
+        (
+            "/",
+            "([a b])",
+            "Returns a divided by b, rounded towards zero.",
+        ),
+        (
+            "prn",
+            "([& xs])",
+            "Prints xs readably, separated by spaces, followed by a newline.",
+        ),
+        (
+            "pr-str",
+            "([& xs])",
+            "Returns xs printed readably and separated by spaces.",
+        ),
+        (
+            "str",
+            "([& xs])",
+            "Returns the concatenation of xs printed as by println.",
+        ),
+        (
+            "println",
+            "([& xs])",
+            "Prints xs separated by spaces, followed by a newline.",
+        ),
+        (
+            "print",
+            "([& xs])",
+            "Prints xs separated by spaces, without a newline.",
+        ),
+        (
+            "eprintln",
+            "([& xs])",
+            "Prints xs to *err* separated by spaces, followed by a newline.",
+        ),
+        ("flush", "([])", "Flushes *out*."),
+        (
+            "read-line",
+            "([] [port])",
+            "Reads a line from port or *in*, or returns nil at end of input.",
+        ),
+        (
+            "string-writer",
+            "([])",
+            "Returns a port collecting what is written to it.",
+        ),
+        ("string-reader", "([s])", "Returns a port reading from s."),
+        (
+            "written-str",
+            "([writer])",
+            "Returns what has been written to a string writer.",
+        ),
+        ("port?", "([x])", "Returns true if x is a port."),
+        ("list", "([& xs])", "Returns a list of xs."),
+        ("list?", "([x])", "Returns true if x is a list."),
+        ("empty?", "([coll])", "Returns true if coll has no items."),
+        ("count", "([coll])", "Returns the number of items in coll."),
+        ("=", "([a b])", "Returns true if a and b are equal."),
+        ("<", "([a b])", "Returns true if a is less than b."),
+        (
+            "<=",
+            "([a b])",
+            "Returns true if a is less than or equal to b.",
+        ),
+        (">", "([a b])", "Returns true if a is greater than b."),
+        (
+            ">=",
+            "([a b])",
+            "Returns true if a is greater than or equal to b.",
+        ),
+        (
+            "read-string",
+            "([s])",
+            "Reads the first form in the string s.",
+        ),
+        (
+            "edn-read-string",
+            "([s] [options s])",
+            "Reads the first form in the string s as EDN, without evaluating anything, or returns nil. Tagged literals are read by the functions in the :readers map of options, keyed by tag name, then by the :default function, called with the tag and the value.",
+        ),
+        (
+            "read-string-all",
+            "([s])",
+            "Reads every form in the string s into a list.",
+        ),
+        (
+            "read-all",
+            "([filename])",
+            "Reads every form in a file into a list.",
+        ),
+        (
+            "slurp",
+            "([filename])",
+            "Returns the contents of a file as a string.",
+        ),
+        ("atom", "([x])", "Returns an atom holding x."),
+        ("atom?", "([x])", "Returns true if x is an atom."),
+        ("deref", "([atom])", "Returns the value held by atom."),
+        (
+            "reset!",
+            "([atom x])",
+            "Sets the value of atom to x and returns x.",
+        ),
+        (
+            "swap!",
+            "([atom f & args])",
+            "Sets the value of atom to (apply f value args) and returns it.",
+        ),
+        (
+            "cons",
+            "([x coll])",
+            "Returns a list of x followed by the items of coll.",
+        ),
+        (
+            "concat",
+            "([& colls])",
+            "Returns a list of the items of every coll.",
+        ),
+        ("vec", "([coll])", "Returns a vector of the items of coll."),
+        (
+            "nth",
+            "([coll index])",
+            "Returns the item of coll at index, throwing when out of range.",
+        ),
+        (
+            "first",
+            "([coll])",
+            "Returns the first item of coll, or nil when empty.",
+        ),
+        (
+            "rest",
+            "([coll])",
+            "Returns a list of the items of coll after the first.",
+        ),
+        ("throw", "([x])", "Throws x as an exception."),
+        (
+            "apply",
+            "([f & args coll])",
+            "Calls f with args followed by the items of coll.",
+        ),
+        (
+            "map",
+            "([f] [f coll])",
+            "Returns the results of calling f on each item of coll, or a mapping transducer.",
+        ),
+        ("nil?", "([x])", "Returns true if x is nil."),
+        ("true?", "([x])", "Returns true if x is true."),
+        ("false?", "([x])", "Returns true if x is false."),
+        ("symbol?", "([x])", "Returns true if x is a symbol."),
+        (
+            "symbol",
+            "([name])",
+            "Returns the symbol with the given name.",
+        ),
+        (
+            "keyword",
+            "([name])",
+            "Returns the keyword with the given name.",
+        ),
+        ("keyword?", "([x])", "Returns true if x is a keyword."),
+        ("vector", "([& xs])", "Returns a vector of xs."),
+        ("vector?", "([x])", "Returns true if x is a vector."),
+        (
+            "sequential?",
+            "([x])",
+            "Returns true if x is a list, vector or lazy sequence.",
+        ),
+        (
+            "hash-map",
+            "([& kvs])",
+            "Returns a hash-map of the given keys and values.",
+        ),
+        ("hash-set", "([& xs])", "Returns a set of xs."),
+        ("set", "([coll])", "Returns a set of the items of coll."),
+        ("set?", "([x])", "Returns true if x is a set."),
+        ("disj", "([s & xs])", "Returns s without xs."),
+        ("map?", "([x])", "Returns true if x is a hash-map."),
+        (
+            "assoc",
+            "([m & kvs])",
+            "Returns m with the given keys set to the given values.",
+        ),
+        ("dissoc", "([m & ks])", "Returns m without the given keys."),
+        ("get", "([m k])", "Returns the value of k in m, or nil. For a set, returns k if it is in it."),
+        ("contains?", "([m k])", "Returns true if m has the key k, or the set m has k."),
+        ("keys", "([m])", "Returns a list of the keys of m."),
+        ("vals", "([m])", "Returns a list of the values of m."),
+        (
+            "readline",
+            "([prompt])",
+            "Reads a line from the terminal, or returns nil at end of input.",
+        ),
+        ("meta", "([x])", "Returns the metadata of x."),
+        (
+            "with-meta",
+            "([x m])",
+            "Returns x with its metadata replaced by m.",
+        ),
+        (
+            "time-ms",
+            "([])",
+            "Returns the current time in milliseconds.",
+        ),
+        (
+            "conj",
+            "([coll & xs])",
+            "Adds xs to the front of a list, the end of a vector or a set.",
+        ),
+        ("string?", "([x])", "Returns true if x is a string."),
+        ("char", "([x])", "Returns the character with code point x."),
+        ("char?", "([x])", "Returns true if x is a character."),
+        ("int", "([x])", "Returns the code point of a character."),
+        ("number?", "([x])", "Returns true if x is a number."),
+        (
+            "fn?",
+            "([x])",
+            "Returns true if x is a function that isn't a macro.",
+        ),
+        ("macro?", "([x])", "Returns true if x is a macro."),
+        (
+            "gensym",
+            "([] [prefix])",
+            "Returns a new symbol with a unique name.",
+        ),
+        (
+            "fn-name",
+            "([f])",
+            "Returns the name of f, or nil when it has none.",
+        ),
+        (
+            "arglists",
+            "([f])",
+            "Returns the parameter vectors of f, or nil when unknown.",
+        ),
+        (
+            "seq",
+            "([coll])",
+            "Returns a sequence of the items of coll, or nil when empty.",
+        ),
+        (
+            "lazy-seq*",
+            "([f])",
+            "Returns a lazy sequence of the items of the seqable value f returns.",
+        ),
+        (
+            "doall",
+            "([coll])",
+            "Realizes every item of a lazy sequence and returns it.",
+        ),
+        (
+            "range",
+            "([] [end] [start end] [start end step])",
+            "Returns a lazy sequence of numbers from start to end, exclusive.",
+        ),
+        (
+            "iterate",
+            "([f x])",
+            "Returns a lazy sequence of x, (f x), (f (f x)) and so on.",
+        ),
+        (
+            "repeat",
+            "([x] [n x])",
+            "Returns a lazy sequence of x, n times or forever.",
+        ),
+        (
+            "cycle",
+            "([coll])",
+            "Returns a lazy sequence repeating the items of coll forever.",
+        ),
+        (
+            "take",
+            "([n] [n coll])",
+            "Returns the first n items of coll, or a transducer.",
+        ),
+        (
+            "drop",
+            "([n] [n coll])",
+            "Returns the items of coll after the first n, or a transducer.",
+        ),
+        (
+            "take-while",
+            "([pred] [pred coll])",
+            "Returns the leading items of coll for which pred is true, or a transducer.",
+        ),
+        (
+            "filter",
+            "([pred] [pred coll])",
+            "Returns the items of coll for which pred is true, or a transducer.",
+        ),
+    ]
+}
+
 fn binary_number_op(args: &Vec<MalType>, op: fn(i64, i64) -> i64) -> Result<MalType, MalType> {
     match (&args[0], &args[1]) {
         (MalType::Number(a), MalType::Number(b)) => Ok(MalType::Number(op(*a, *b))),
@@ -557,11 +1144,169 @@ fn unary_op(
     op(&args[0])
 }
 
-fn join(v: &Vec<MalType>, print_readably: bool, separator: &str) -> String {
-    v.iter()
+/// Converts the items of `v` to strings in full, separated by `separator`.
+fn join(v: &Vec<MalType>, print_readably: bool, separator: &str) -> Result<String, MalType> {
+    v.iter().try_for_each(realize)?;
+    Ok(v.iter()
         .map(|v| pr_str(v, print_readably))
         .collect::<Vec<String>>()
-        .join(separator)
+        .join(separator))
+}
+
+/// Prints the items of `v` as the printing functions do, separated by
+/// `separator`.
+fn print_join(v: &[MalType], print_readably: bool, separator: &str) -> Result<String, MalType> {
+    v.iter().try_for_each(realize_printed)?;
+    Ok(v.iter()
+        .map(|v| print_str(v, print_readably))
+        .collect::<Vec<String>>()
+        .join(separator))
+}
+
+/// Calls `args[0]` with the rest of `args`, spreading the last one.
+pub fn apply(args: &[MalType]) -> Result<MalType, MalType> {
+    let last_index = args.len() - 1;
+    let f_args = match &args[last_index] {
+        MalType::List(list, _) | MalType::Vector(list, _) => {
+            let mut f_args = Vec::new();
+            f_args.extend(args[1..last_index].to_vec());
+            f_args.extend(list.clone());
+            f_args
+        }
+        MalType::LazySeq(_) => {
+            let mut f_args = Vec::new();
+            f_args.extend(args[1..last_index].to_vec());
+            f_args.extend(seq_to_vec(&args[last_index])?);
+            f_args
+        }
+        last_arg => return error(format!("Expected list or vector, but got {}", &last_arg)),
+    };
+    match &args[0] {
+        MalType::Closure(closure, _) => closure.apply(&f_args),
+        MalType::Function(f, _, _) => f(&f_args),
+        _ => error(format!("Expected function, but got {}", &args[0])),
+    }
+}
+
+pub fn transducer(xform: Xform) -> MalType {
+    MalType::Transducer(Rc::new(vec![xform]))
+}
+
+pub fn lazy_cons(first: MalType, rest: MalType) -> Result<MalType, MalType> {
+    Ok(MalType::LazySeq(LazySeq::cons(first, rest)))
+}
+
+fn lazy_map(f: MalType, coll: MalType) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || match seq_step(&coll)? {
+        Some((first, rest)) => lazy_cons(f.apply(&vec![first])?, lazy_map(f.clone(), rest)),
+        None => Ok(MalType::Nil),
+    }))
+}
+
+fn range(start: i64, end: Option<i64>, step: i64) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || {
+        let done = match end {
+            Some(end) if step > 0 => start >= end,
+            Some(end) if step < 0 => start <= end,
+            _ => false,
+        };
+        if done {
+            Ok(MalType::Nil)
+        } else {
+            lazy_cons(MalType::Number(start), range(start + step, end, step))
+        }
+    }))
+}
+
+fn iterate(f: MalType, x: MalType) -> MalType {
+    MalType::LazySeq(LazySeq::cons(
+        x.clone(),
+        MalType::LazySeq(LazySeq::new(move || {
+            Ok(iterate(f.clone(), f.apply(&vec![x.clone()])?))
+        })),
+    ))
+}
+
+fn repeat(n: Option<i64>, x: MalType) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || match n {
+        Some(n) if n <= 0 => Ok(MalType::Nil),
+        _ => lazy_cons(x.clone(), repeat(n.map(|n| n - 1), x.clone())),
+    }))
+}
+
+fn cycle(list: Vec<MalType>, index: usize) -> MalType {
+    if list.is_empty() {
+        return MalType::Nil;
+    }
+
+    MalType::LazySeq(LazySeq::new(move || {
+        lazy_cons(
+            list[index].clone(),
+            cycle(list.clone(), (index + 1) % list.len()),
+        )
+    }))
+}
+
+fn take(n: i64, coll: MalType) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || {
+        if n <= 0 {
+            return Ok(MalType::Nil);
+        }
+
+        match seq_step(&coll)? {
+            Some((first, rest)) => lazy_cons(first, take(n - 1, rest)),
+            None => Ok(MalType::Nil),
+        }
+    }))
+}
+
+fn drop(n: i64, coll: MalType) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || {
+        let mut coll = coll.clone();
+        for _ in 0..n {
+            match seq_step(&coll)? {
+                Some((_, rest)) => coll = rest,
+                None => return Ok(MalType::Nil),
+            }
+        }
+        Ok(coll)
+    }))
+}
+
+fn take_while(pred: MalType, coll: MalType) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || match seq_step(&coll)? {
+        Some((first, rest)) => match pred.apply(&vec![first.clone()])? {
+            MalType::Nil | MalType::False => Ok(MalType::Nil),
+            _ => lazy_cons(first, take_while(pred.clone(), rest)),
+        },
+        None => Ok(MalType::Nil),
+    }))
+}
+
+/// Lazily keeps the elements of `coll` for which `pred` is truthy, or falsy
+/// when `keep` is false.
+pub fn filter(pred: MalType, coll: MalType, keep: bool) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || {
+        let mut coll = coll.clone();
+        while let Some((first, rest)) = seq_step(&coll)? {
+            let matched = !matches!(
+                pred.apply(&vec![first.clone()])?,
+                MalType::Nil | MalType::False
+            );
+            if matched == keep {
+                return lazy_cons(first, filter(pred.clone(), rest, keep));
+            }
+            coll = rest;
+        }
+        Ok(MalType::Nil)
+    }))
+}
+
+fn port_arg(value: &MalType) -> Result<&Port, MalType> {
+    match value {
+        MalType::Port(port) => Ok(port),
+        _ => error(format!("Expected port, but got {}", value)),
+    }
 }
 
 fn read_file(filename: &str) -> Result<MalType, MalType> {
@@ -0,0 +1,41 @@
+use std::{cell::RefCell, collections::HashSet};
+
+thread_local! {
+    /// Vars defined with `^:dynamic`, which `binding` may rebind.
+    static DYNAMIC: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    /// Vars rebound by the enclosing `binding` forms, innermost last.
+    static BOUND: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Marks `name` as dynamic. A var stays dynamic when it is redefined.
+pub fn declare_dynamic(name: &str) {
+    DYNAMIC.with(|dynamic| dynamic.borrow_mut().insert(name.to_string()));
+}
+
+/// Names of the vars defined with `^:dynamic`.
+pub fn dynamic_vars() -> Vec<String> {
+    DYNAMIC.with(|dynamic| dynamic.borrow().iter().cloned().collect())
+}
+
+pub fn is_dynamic(name: &str) -> bool {
+    DYNAMIC.with(|dynamic| dynamic.borrow().contains(name))
+}
+
+/// Records the vars rebound by a `binding` form until `pop_bindings`.
+pub fn push_bindings(names: &[String]) {
+    BOUND.with(|bound| bound.borrow_mut().extend_from_slice(names));
+}
+
+pub fn pop_bindings(count: usize) {
+    BOUND.with(|bound| {
+        let mut bound = bound.borrow_mut();
+        let len = bound.len();
+        bound.truncate(len - count);
+    });
+}
+
+/// Whether `name` is rebound by an enclosing `binding`, so `set!` may change
+/// it.
+pub fn is_bound(name: &str) -> bool {
+    BOUND.with(|bound| bound.borrow().iter().any(|bound| bound == name))
+}
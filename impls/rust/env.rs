@@ -50,6 +50,11 @@ impl Env {
         self.data.insert(key, value);
     }
 
+    /// Removes `key` from this environment, not from the outer ones.
+    pub fn remove(&mut self, key: &str) -> Option<MalType> {
+        self.data.remove(key)
+    }
+
     pub fn get(&self, key: &str) -> Option<MalType> {
         if let Some(value) = self.data.get(key) {
             return Some(value.clone());
@@ -60,6 +65,11 @@ impl Env {
             .map(|outer| outer.borrow().get(key))
             .flatten()
     }
+
+    /// Symbols defined in this environment, not including the outer ones.
+    pub fn symbols(&self) -> Vec<String> {
+        self.data.keys().cloned().collect()
+    }
 }
 
 impl Display for Env {
@@ -0,0 +1,192 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    rc::Rc,
+    time::UNIX_EPOCH,
+};
+
+use indexmap::IndexMap;
+
+use crate::printer::pr_str;
+use crate::types::{error, seq_to_vec, Function, Hashable, LazySeq, MalType};
+
+pub fn ns() -> HashMap<&'static str, Function> {
+    let mut ns: HashMap<&'static str, Function> = HashMap::new();
+    ns.insert("spit", |args| {
+        let path = args[0].as_string()?;
+        let append = option(&args[2..], "append")?;
+        let content = match &args[1] {
+            MalType::String(content) => content.clone(),
+            value => pr_str(value, false),
+        };
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .or_else(|err| io_error(path, err))?;
+        Ok(MalType::Nil)
+    });
+    ns.insert("slurp-bytes", |args| {
+        let path = args[0].as_string()?;
+        let bytes = fs::read(path).or_else(|err| io_error(path, err))?;
+        Ok(MalType::Vector(
+            bytes
+                .into_iter()
+                .map(|byte| MalType::Number(byte as i64))
+                .collect(),
+            None,
+        ))
+    });
+    ns.insert("spit-bytes", |args| {
+        let path = args[0].as_string()?;
+        let bytes = seq_to_vec(&args[1])?
+            .iter()
+            .map(|byte| match byte {
+                MalType::Number(byte @ 0..=255) => Ok(*byte as u8),
+                value => error(format!("Expected byte, but got {}", value)),
+            })
+            .collect::<Result<Vec<u8>, MalType>>()?;
+        fs::write(path, bytes).or_else(|err| io_error(path, err))?;
+        Ok(MalType::Nil)
+    });
+    ns.insert("line-seq", |args| {
+        let path = args[0].as_string()?;
+        let file = File::open(path).or_else(|err| io_error(path, err))?;
+        Ok(lines(
+            path.clone(),
+            Rc::new(RefCell::new(BufReader::new(file))),
+        ))
+    });
+    ns.insert("file-exists?", |args| {
+        Ok(boolean(fs::metadata(args[0].as_string()?).is_ok()))
+    });
+    ns.insert("file-info", |args| {
+        let path = args[0].as_string()?;
+        let metadata = fs::metadata(path).or_else(|err| io_error(path, err))?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(
+                MalType::Nil,
+                |time| MalType::Number(time.as_millis() as i64),
+            );
+        Ok(MalType::Hashmap(
+            IndexMap::from([
+                (
+                    Hashable::Keyword("size".to_string()),
+                    MalType::Number(metadata.len() as i64),
+                ),
+                (Hashable::Keyword("mtime".to_string()), mtime),
+                (
+                    Hashable::Keyword("dir?".to_string()),
+                    boolean(metadata.is_dir()),
+                ),
+            ]),
+            None,
+        ))
+    });
+    ns.insert("list-dir", |args| {
+        let path = args[0].as_string()?;
+        let mut names = fs::read_dir(path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+                    .collect::<io::Result<Vec<String>>>()
+            })
+            .or_else(|err| io_error(path, err))?;
+        names.sort();
+        Ok(MalType::Vector(
+            names.into_iter().map(MalType::String).collect(),
+            None,
+        ))
+    });
+    ns.insert("mkdir", |args| {
+        let path = args[0].as_string()?;
+        fs::create_dir_all(path).or_else(|err| io_error(path, err))?;
+        Ok(MalType::Nil)
+    });
+    ns.insert("delete-file", |args| {
+        let path = args[0].as_string()?;
+        let result = match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir(path),
+            _ => fs::remove_file(path),
+        };
+        match (result, args.get(1)) {
+            (Ok(()), _) | (Err(_), Some(MalType::True)) => Ok(MalType::Nil),
+            (Err(err), _) => io_error(path, err),
+        }
+    });
+    ns
+}
+
+/// Arglists and docstrings of the builtins in `ns()`.
+pub fn docs() -> Vec<(&'static str, &'static str, &'static str)> {
+    vec![
+        ("spit", "([path content & options])", "Writes content, printed as by str, to a file, replacing it unless :append is true."),
+        ("slurp-bytes", "([path])", "Returns the contents of a file as a vector of bytes."),
+        ("spit-bytes", "([path bytes])", "Writes a sequence of bytes to a file, replacing it."),
+        ("line-seq", "([path])", "Returns a lazy sequence of the lines of a file."),
+        ("file-exists?", "([path])", "Returns true if a file or directory exists at path."),
+        ("file-info", "([path])", "Returns a map of the :size, :mtime in milliseconds since the epoch and :dir? of a file."),
+        ("list-dir", "([path])", "Returns the sorted names of the entries of a directory."),
+        ("mkdir", "([path])", "Creates a directory and any missing parents."),
+        ("delete-file", "([path] [path silently])", "Deletes a file or empty directory, throwing on failure unless silently is true."),
+    ]
+}
+
+fn boolean(value: bool) -> MalType {
+    if value {
+        MalType::True
+    } else {
+        MalType::False
+    }
+}
+
+fn io_error<T>(path: &str, err: io::Error) -> Result<T, MalType> {
+    error(format!("{}: {}", path, err))
+}
+
+/// Reads the boolean `name` from keyword options like `:append true`.
+fn option(options: &[MalType], name: &str) -> Result<bool, MalType> {
+    for pair in options.chunks(2) {
+        match pair {
+            [MalType::Keyword(key), value] if key == name => {
+                return Ok(!matches!(value, MalType::Nil | MalType::False))
+            }
+            [MalType::Keyword(_), _] => {}
+            [key @ MalType::Keyword(_)] => {
+                return error(format!("Missing value for option {}", key))
+            }
+            _ => return error(format!("Expected keyword option, but got {}", pair[0])),
+        }
+    }
+    Ok(false)
+}
+
+/// The lines still to be read from `reader`, without their line endings.
+fn lines(path: String, reader: Rc<RefCell<BufReader<File>>>) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || {
+        let mut line = String::new();
+        match reader.borrow_mut().read_line(&mut line) {
+            Ok(0) => return Ok(MalType::Nil),
+            Ok(_) => {}
+            Err(err) => return io_error(&path, err),
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(MalType::LazySeq(LazySeq::cons(
+            MalType::String(line),
+            lines(path.clone(), reader.clone()),
+        )))
+    }))
+}
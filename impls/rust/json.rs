@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use indexmap::IndexMap;
+
+use crate::printer::pr_str;
+use crate::types::{error, seq_to_vec, Function, Hashable, MalType};
+
+pub fn ns() -> HashMap<&'static str, Function> {
+    let mut ns: HashMap<&'static str, Function> = HashMap::new();
+    ns.insert("json-parse", |args| {
+        let mut parser = Parser {
+            chars: args[0].as_string()?.chars().collect(),
+            position: 0,
+            keywords: option(&args[1..], "keywords")?,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.position < parser.chars.len() {
+            return parser.fail("end of input");
+        }
+        Ok(value)
+    });
+    ns.insert("json-stringify", |args| {
+        let indent = option(&args[1..], "pretty")?.then_some(0);
+        let mut result = String::new();
+        stringify(&args[0], indent, &mut result)?;
+        Ok(MalType::String(result))
+    });
+    ns
+}
+
+/// Arglists and docstrings of the builtins in `ns()`.
+pub fn docs() -> Vec<(&'static str, &'static str, &'static str)> {
+    vec![
+        ("json-parse", "([s & options])", "Parses a JSON document into maps, vectors, strings, numbers, booleans and nil. Object keys are strings, or keywords when :keywords is true. Numbers must be integers that fit in 64 bits; others, like 1.5, 1.0 or 1e2, throw."),
+        ("json-stringify", "([x & options])", "Encodes x as JSON, indented over several lines when :pretty is true. Keywords and symbols are encoded as strings, and sets as arrays."),
+    ]
+}
+
+/// Reads the boolean `name` from keyword options like `:pretty true`.
+fn option(options: &[MalType], name: &str) -> Result<bool, MalType> {
+    for pair in options.chunks(2) {
+        match pair {
+            [MalType::Keyword(key), value] if key == name => {
+                return Ok(!matches!(value, MalType::Nil | MalType::False))
+            }
+            [MalType::Keyword(_), _] => {}
+            [key @ MalType::Keyword(_)] => {
+                return error(format!("Missing value for option {}", key))
+            }
+            _ => return error(format!("Expected keyword option, but got {}", pair[0])),
+        }
+    }
+    Ok(false)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    keywords: bool,
+}
+
+impl Parser {
+    fn fail<T>(&self, expected: &str) -> Result<T, MalType> {
+        match self.chars.get(self.position) {
+            Some(c) => error(format!(
+                "Invalid JSON: expected {}, but got '{}' at position {}",
+                expected, c, self.position
+            )),
+            None => error(format!(
+                "Invalid JSON: expected {}, but got end of input",
+                expected
+            )),
+        }
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    /// Consumes `c` if it is the next character.
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), MalType> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.fail(&format!("'{}'", c))
+        }
+    }
+
+    fn value(&mut self) -> Result<MalType, MalType> {
+        self.whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(MalType::String),
+            Some('-' | '0'..='9') => self.number(),
+            Some('t') => self.literal("true", MalType::True),
+            Some('f') => self.literal("false", MalType::False),
+            Some('n') => self.literal("null", MalType::Nil),
+            _ => self.fail("a value"),
+        }
+    }
+
+    fn literal(&mut self, name: &str, value: MalType) -> Result<MalType, MalType> {
+        for c in name.chars() {
+            if !self.eat(c) {
+                return self.fail(name);
+            }
+        }
+        Ok(value)
+    }
+
+    fn object(&mut self) -> Result<MalType, MalType> {
+        self.expect('{')?;
+        let mut map = IndexMap::new();
+        self.whitespace();
+        if self.eat('}') {
+            return Ok(MalType::Hashmap(map, None));
+        }
+        loop {
+            self.whitespace();
+            if self.peek() != Some('"') {
+                return self.fail("a string key");
+            }
+            let key = self.string()?;
+            let key = if self.keywords {
+                Hashable::Keyword(key)
+            } else {
+                Hashable::String(key)
+            };
+            self.whitespace();
+            self.expect(':')?;
+            map.insert(key, self.value()?);
+            self.whitespace();
+            if self.eat('}') {
+                return Ok(MalType::Hashmap(map, None));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn array(&mut self) -> Result<MalType, MalType> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.whitespace();
+        if self.eat(']') {
+            return Ok(MalType::Vector(items, None));
+        }
+        loop {
+            items.push(self.value()?);
+            self.whitespace();
+            if self.eat(']') {
+                return Ok(MalType::Vector(items, None));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, MalType> {
+        self.expect('"')?;
+        let mut result = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return self.fail("'\"'"),
+            };
+            self.position += 1;
+            match c {
+                '"' => return Ok(result),
+                '\\' => {
+                    let escape = self.peek();
+                    self.position += 1;
+                    match escape {
+                        Some('"') => result.push('"'),
+                        Some('\\') => result.push('\\'),
+                        Some('/') => result.push('/'),
+                        Some('b') => result.push('\u{8}'),
+                        Some('f') => result.push('\u{c}'),
+                        Some('n') => result.push('\n'),
+                        Some('r') => result.push('\r'),
+                        Some('t') => result.push('\t'),
+                        Some('u') => result.push(self.unicode_escape()?),
+                        _ => {
+                            self.position -= 1;
+                            return self.fail("an escape sequence");
+                        }
+                    }
+                }
+                c if c < ' ' => {
+                    self.position -= 1;
+                    return self.fail("an escaped control character");
+                }
+                c => result.push(c),
+            }
+        }
+    }
+
+    /// Reads the digits of a `\u` escape, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, MalType> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !(self.eat('\\') && self.eat('u')) {
+                return self.fail("a low surrogate");
+            }
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return self.fail("a low surrogate");
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => self.fail("a valid code point"),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, MalType> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return self.fail("a hexadecimal digit"),
+            }
+            self.position += 1;
+        }
+        Ok(code)
+    }
+
+    /// Reads a number. Mal only has integers, so a number with a fraction or
+    /// exponent, or one out of range, is unsupported.
+    fn number(&mut self) -> Result<MalType, MalType> {
+        let start = self.position;
+        self.eat('-');
+        if !self.digits() {
+            return self.fail("a digit");
+        }
+        let mut integer = true;
+        if self.eat('.') {
+            integer = false;
+            if !self.digits() {
+                return self.fail("a digit");
+            }
+        }
+        if self.eat('e') || self.eat('E') {
+            integer = false;
+            if !self.eat('+') {
+                self.eat('-');
+            }
+            if !self.digits() {
+                return self.fail("a digit");
+            }
+        }
+
+        let text: String = self.chars[start..self.position].iter().collect();
+        match text.parse::<i64>() {
+            Ok(value) if integer => Ok(MalType::Number(value)),
+            _ => error(format!(
+                "Unsupported JSON number {}: only 64-bit integers are supported",
+                text
+            )),
+        }
+    }
+
+    fn digits(&mut self) -> bool {
+        let start = self.position;
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.position += 1;
+        }
+        self.position > start
+    }
+}
+
+/// Appends `value` encoded as JSON to `result`, indenting nested values by
+/// two more columns than `indent` unless it is `None`.
+fn stringify(value: &MalType, indent: Option<usize>, result: &mut String) -> Result<(), MalType> {
+    match value {
+        MalType::Nil => result.push_str("null"),
+        MalType::True => result.push_str("true"),
+        MalType::False => result.push_str("false"),
+        MalType::Number(value) => result.push_str(&value.to_string()),
+        MalType::String(value) | MalType::Keyword(value) | MalType::Symbol(value) => {
+            quote(value, result)
+        }
+        MalType::Char(c) => quote(&c.to_string(), result),
+        MalType::List(_, _) | MalType::Vector(_, _) | MalType::Set(_, _) | MalType::LazySeq(_) => {
+            let items = seq_to_vec(value)?;
+            let mut items = items.iter();
+            sequence('[', ']', indent, result, |indent, result| {
+                match items.next() {
+                    Some(item) => stringify(item, indent, result).map(|_| true),
+                    None => Ok(false),
+                }
+            })?;
+        }
+        MalType::Hashmap(map, _) => {
+            let mut entries = map.iter();
+            sequence('{', '}', indent, result, |indent, result| {
+                let (key, value) = match entries.next() {
+                    Some(entry) => entry,
+                    None => return Ok(false),
+                };
+                match key {
+                    Hashable::Keyword(key) | Hashable::String(key) | Hashable::Symbol(key) => {
+                        quote(key, result)
+                    }
+                    key => quote(&pr_str(&key.to_mal(), false), result),
+                }
+                result.push_str(if indent.is_some() { ": " } else { ":" });
+                stringify(value, indent, result).map(|_| true)
+            })?;
+        }
+        value => return error(format!("Cannot encode {} as JSON", pr_str(value, true))),
+    }
+    Ok(())
+}
+
+/// Appends the items `item` writes until it returns false, separated by
+/// commas and, when pretty printing, on their own indented lines.
+fn sequence(
+    open: char,
+    close: char,
+    indent: Option<usize>,
+    result: &mut String,
+    mut item: impl FnMut(Option<usize>, &mut String) -> Result<bool, MalType>,
+) -> Result<(), MalType> {
+    result.push(open);
+    let inner = indent.map(|indent| indent + 2);
+    let mut empty = true;
+    loop {
+        let mark = result.len();
+        if !empty {
+            result.push(',');
+        }
+        if let Some(inner) = inner {
+            result.push('\n');
+            result.push_str(&" ".repeat(inner));
+        }
+        if !item(inner, result)? {
+            result.truncate(mark);
+            break;
+        }
+        empty = false;
+    }
+    if let (Some(indent), false) = (indent, empty) {
+        result.push('\n');
+        result.push_str(&" ".repeat(indent));
+    }
+    result.push(close);
+    Ok(())
+}
+
+fn quote(value: &str, result: &mut String) {
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c < ' ' => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+}
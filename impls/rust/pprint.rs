@@ -0,0 +1,367 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::printer::{out_port, print_length, print_level, print_str};
+use crate::types::{error, seq_step, Function, Hashable, MalType};
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+pub fn ns() -> HashMap<&'static str, Function> {
+    let mut ns: HashMap<&'static str, Function> = HashMap::new();
+    ns.insert("pprint", |args| {
+        out_port().write(&format!("{}\n", pprint(&args[0], width_arg(args.get(1))?)?))?;
+        Ok(MalType::Nil)
+    });
+    ns.insert("pprint-str", |args| {
+        pprint(&args[0], width_arg(args.get(1))?).map(MalType::String)
+    });
+    ns
+}
+
+/// Arglists and docstrings of the builtins in `ns()`.
+pub fn docs() -> Vec<(&'static str, &'static str, &'static str)> {
+    vec![
+        (
+            "pprint",
+            "([x] [x width])",
+            "Prints x readably, breaking it over lines no wider than width.",
+        ),
+        (
+            "pprint-str",
+            "([x] [x width])",
+            "Returns x pretty printed as by pprint.",
+        ),
+    ]
+}
+
+fn width_arg(value: Option<&MalType>) -> Result<usize, MalType> {
+    match value {
+        None | Some(MalType::Nil) => Ok(DEFAULT_WIDTH),
+        Some(MalType::Number(width)) if *width > 0 => Ok(*width as usize),
+        Some(value) => error(format!("Expected positive number, but got {}", value)),
+    }
+}
+
+/// Renders `value` readably, breaking it over several lines when it doesn't
+/// fit in `width` columns.
+pub fn pprint(value: &MalType, width: usize) -> Result<String, MalType> {
+    let doc = Builder {
+        length: print_length(),
+        level: print_level(),
+        depth: 0,
+        atoms: Vec::new(),
+    }
+    .build(value)?;
+    Ok(layout(&doc, width))
+}
+
+/// A document in the style of Wadler's "A prettier printer": every `Line`
+/// inside a `Group` is rendered as a space when the whole group fits on the
+/// rest of the line, and as a newline otherwise.
+enum Doc {
+    Text(String),
+    Line,
+    Nest(usize, Box<Doc>),
+    /// Indents the lines of the inner document to the current column.
+    Align(Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+fn text(value: &str) -> Doc {
+    Doc::Text(value.to_string())
+}
+
+/// Number of arguments kept on the first line of a special form; the rest of
+/// the form is indented by two columns.
+fn special_form(name: &str) -> Option<usize> {
+    match name {
+        "do" | "try*" | "cond" => Some(0),
+        "def!" | "defmacro!" | "let*" | "fn*" | "if" | "catch*" | "when" | "when-not" | "let"
+        | "loop" | "binding" | "if-let" | "when-let" | "case" | "doto" | "fn" | "deftest"
+        | "testing" => Some(1),
+        "defn" | "defmacro" | "condp" => Some(2),
+        _ => None,
+    }
+}
+
+fn is_binding_form(name: &str) -> bool {
+    matches!(
+        name,
+        "let*" | "let" | "loop" | "binding" | "if-let" | "when-let"
+    )
+}
+
+/// Builds the document for a value, honouring `*print-length*` and
+/// `*print-level*` and cutting cycles through atoms like `print_str` does.
+struct Builder {
+    length: Option<usize>,
+    level: Option<usize>,
+    depth: usize,
+    atoms: Vec<*const RefCell<MalType>>,
+}
+
+impl Builder {
+    fn build(&mut self, value: &MalType) -> Result<Doc, MalType> {
+        let list = match value {
+            MalType::List(list, _) | MalType::Vector(list, _) => list.clone(),
+            MalType::Hashmap(map, _) => map
+                .iter()
+                .flat_map(|(key, value)| [key.to_mal(), value.clone()])
+                .collect(),
+            MalType::Set(set, _) => set.iter().map(Hashable::to_mal).collect(),
+            MalType::LazySeq(_) => self.take_lazy(value)?,
+            MalType::Atom(v) if self.atoms.contains(&Rc::as_ptr(v)) => return Ok(text("#<cycle>")),
+            MalType::Atom(v) => {
+                self.atoms.push(Rc::as_ptr(v));
+                let doc = self.build(&v.borrow());
+                self.atoms.pop();
+                return Ok(Doc::Concat(vec![
+                    text("(atom "),
+                    Doc::Align(Box::new(doc?)),
+                    text(")"),
+                ]));
+            }
+            _ => return Ok(Doc::Text(print_str(value, true))),
+        };
+        if self.level.is_some_and(|level| self.depth >= level) {
+            return Ok(text("..."));
+        }
+
+        let limit = match (value, self.length) {
+            (MalType::Hashmap(_, _), Some(length)) => length.saturating_mul(2),
+            (_, Some(length)) => length,
+            (_, None) => usize::MAX,
+        };
+        let more = list.len() > limit;
+        let list = &list[..list.len().min(limit)];
+
+        self.depth += 1;
+        let doc = match value {
+            MalType::List(_, _) | MalType::LazySeq(_) => match list.first() {
+                Some(MalType::Symbol(name)) if special_form(name).is_some() => {
+                    self.form_doc(name, list, special_form(name).unwrap(), more)
+                }
+                Some(MalType::Symbol(_)) if list.len() > 1 => self.call_doc(list, more),
+                _ => self.items_doc("(", list, more, ")"),
+            },
+            MalType::Vector(_, _) => self.items_doc("[", list, more, "]"),
+            MalType::Set(_, _) => self.items_doc("#{", list, more, "}"),
+            _ => self.pairs(list, more).map(|docs| seq_doc("{", docs, "}")),
+        };
+        self.depth -= 1;
+        doc
+    }
+
+    /// Realizes the elements of a lazy sequence that will be printed, plus
+    /// one more to know whether it was truncated.
+    fn take_lazy(&self, value: &MalType) -> Result<Vec<MalType>, MalType> {
+        let limit = self.length.map_or(usize::MAX, |length| length + 1);
+        let mut result = Vec::new();
+        let mut current = value.clone();
+        while result.len() < limit {
+            match seq_step(&current)? {
+                Some((first, rest)) => {
+                    result.push(first);
+                    current = rest;
+                }
+                None => break,
+            }
+        }
+        Ok(result)
+    }
+
+    fn docs(&mut self, list: &[MalType], more: bool) -> Result<Vec<Doc>, MalType> {
+        let mut docs = list
+            .iter()
+            .map(|value| self.build(value))
+            .collect::<Result<Vec<Doc>, MalType>>()?;
+        if more {
+            docs.push(text("..."));
+        }
+        Ok(docs)
+    }
+
+    /// Keeps each key and value together on a line.
+    fn pairs(&mut self, values: &[MalType], more: bool) -> Result<Vec<Doc>, MalType> {
+        let mut docs = Vec::new();
+        for pair in values.chunks(2) {
+            let mut pair_docs = vec![self.build(&pair[0])?];
+            if let Some(value) = pair.get(1) {
+                pair_docs.push(text(" "));
+                pair_docs.push(self.build(value)?);
+            }
+            docs.push(Doc::Concat(pair_docs));
+        }
+        if more {
+            docs.push(text("..."));
+        }
+        Ok(docs)
+    }
+
+    /// Lays out a list or vector, filling lines when it only holds atoms.
+    fn items_doc(
+        &mut self,
+        open: &str,
+        list: &[MalType],
+        more: bool,
+        close: &str,
+    ) -> Result<Doc, MalType> {
+        let docs = self.docs(list, more)?;
+        if list.iter().any(|value| {
+            matches!(
+                value,
+                MalType::List(_, _)
+                    | MalType::Vector(_, _)
+                    | MalType::Hashmap(_, _)
+                    | MalType::Atom(_)
+                    | MalType::LazySeq(_)
+            )
+        }) {
+            return Ok(seq_doc(open, docs, close));
+        }
+
+        Ok(Doc::Group(Box::new(Doc::Concat(vec![
+            text(open),
+            Doc::Align(Box::new(fill(docs))),
+            text(close),
+        ]))))
+    }
+
+    /// Lays out `(f a b)` with the arguments aligned under the first one.
+    fn call_doc(&mut self, list: &[MalType], more: bool) -> Result<Doc, MalType> {
+        Ok(Doc::Group(Box::new(Doc::Concat(vec![
+            text("("),
+            self.build(&list[0])?,
+            text(" "),
+            Doc::Align(Box::new(join(self.docs(&list[1..], more)?))),
+            text(")"),
+        ]))))
+    }
+
+    fn form_doc(
+        &mut self,
+        name: &str,
+        list: &[MalType],
+        args: usize,
+        more: bool,
+    ) -> Result<Doc, MalType> {
+        let args = args.min(list.len() - 1);
+        let mut head = vec![self.build(&list[0])?];
+        for (i, arg) in list[1..=args].iter().enumerate() {
+            head.push(text(" "));
+            head.push(match arg {
+                MalType::Vector(bindings, _) if i == 0 && is_binding_form(name) => {
+                    seq_doc("[", self.pairs(bindings, false)?, "]")
+                }
+                arg => self.build(arg)?,
+            });
+        }
+
+        let body = &list[args + 1..];
+        let body = if name == "cond" {
+            self.pairs(body, more)?
+        } else {
+            self.docs(body, more)?
+        };
+        let mut docs = vec![Doc::Concat(head)];
+        docs.extend(body);
+
+        Ok(Doc::Group(Box::new(Doc::Concat(vec![
+            text("("),
+            Doc::Align(Box::new(Doc::Nest(1, Box::new(join(docs))))),
+            text(")"),
+        ]))))
+    }
+}
+
+fn join(docs: Vec<Doc>) -> Doc {
+    let mut result = Vec::new();
+    for (i, doc) in docs.into_iter().enumerate() {
+        if i > 0 {
+            result.push(Doc::Line);
+        }
+        result.push(doc);
+    }
+    Doc::Concat(result)
+}
+
+/// Like `join`, but breaks a line only before the items that don't fit on
+/// the current one.
+fn fill(docs: Vec<Doc>) -> Doc {
+    let mut result = Vec::new();
+    for (i, doc) in docs.into_iter().enumerate() {
+        if i > 0 {
+            result.push(Doc::Group(Box::new(Doc::Concat(vec![Doc::Line, doc]))));
+        } else {
+            result.push(doc);
+        }
+    }
+    Doc::Concat(result)
+}
+
+fn seq_doc(open: &str, docs: Vec<Doc>, close: &str) -> Doc {
+    Doc::Group(Box::new(Doc::Concat(vec![
+        text(open),
+        Doc::Align(Box::new(join(docs))),
+        text(close),
+    ])))
+}
+
+fn layout(doc: &Doc, width: usize) -> String {
+    let mut result = String::new();
+    let mut column = 0;
+    let mut stack: Vec<(usize, bool, &Doc)> = vec![(0, false, doc)];
+    while let Some((indent, flat, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(value) => {
+                result.push_str(value);
+                column += value.chars().count();
+            }
+            Doc::Line if flat => {
+                result.push(' ');
+                column += 1;
+            }
+            Doc::Line => {
+                result.push('\n');
+                result.push_str(&" ".repeat(indent));
+                column = indent;
+            }
+            Doc::Nest(nest, doc) => stack.push((indent + nest, flat, doc)),
+            Doc::Align(doc) => stack.push((column, flat, doc)),
+            Doc::Group(doc) => {
+                let flat = flat || fits(width as isize - column as isize, doc, &stack);
+                stack.push((indent, flat, doc));
+            }
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((indent, flat, doc));
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Whether `doc` rendered flat, followed by what remains of the current line,
+/// fits in `remaining` columns.
+fn fits(mut remaining: isize, doc: &Doc, rest: &[(usize, bool, &Doc)]) -> bool {
+    let mut pending: Vec<(bool, &Doc)> = vec![(true, doc)];
+    let mut rest = rest.iter().rev();
+    while remaining >= 0 {
+        let (flat, doc) = match pending.pop() {
+            Some(item) => item,
+            None => match rest.next() {
+                Some((_, flat, doc)) => (*flat, *doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(value) => remaining -= value.chars().count() as isize,
+            Doc::Line if flat => remaining -= 1,
+            Doc::Line => return true,
+            Doc::Nest(_, doc) | Doc::Align(doc) | Doc::Group(doc) => pending.push((flat, doc)),
+            Doc::Concat(docs) => pending.extend(docs.iter().rev().map(|doc| (flat, doc))),
+        }
+    }
+    false
+}
@@ -1,45 +1,235 @@
-use std::fmt::Display;
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+    rc::Rc,
+};
 
-use crate::types::{Hashable, MalType};
+use crate::types::{map_entries, seq_step, Closure, MalType, Port};
 
+thread_local! {
+    static PRINT_LENGTH: Cell<Option<usize>> = const { Cell::new(None) };
+    static PRINT_LEVEL: Cell<Option<usize>> = const { Cell::new(None) };
+    static OUT: RefCell<Port> = const { RefCell::new(Port::Stdout) };
+    static ERR: RefCell<Port> = const { RefCell::new(Port::Stderr) };
+    static IN: RefCell<Port> = const { RefCell::new(Port::Stdin) };
+}
+
+/// Updates the printer limits when `name` is `*print-length*` or
+/// `*print-level*`. Anything but a non-negative number removes the limit.
+pub fn set_print_option(name: &str, value: &MalType) {
+    let limit = match value {
+        MalType::Number(limit) if *limit >= 0 => Some(*limit as usize),
+        _ => None,
+    };
+    match name {
+        "*print-length*" => PRINT_LENGTH.with(|v| v.set(limit)),
+        "*print-level*" => PRINT_LEVEL.with(|v| v.set(limit)),
+        _ => {}
+    }
+}
+
+/// Updates the standard ports when `name` is `*out*`, `*err*` or `*in*`.
+/// Anything but a port is ignored.
+pub fn set_port(name: &str, value: &MalType) {
+    match (name, value) {
+        ("*out*", MalType::Port(port)) => OUT.with(|v| *v.borrow_mut() = port.clone()),
+        ("*err*", MalType::Port(port)) => ERR.with(|v| *v.borrow_mut() = port.clone()),
+        ("*in*", MalType::Port(port)) => IN.with(|v| *v.borrow_mut() = port.clone()),
+        _ => {}
+    }
+}
+
+/// The port `*out*` is bound to.
+pub fn out_port() -> Port {
+    OUT.with(|v| v.borrow().clone())
+}
+
+/// The port `*err*` is bound to.
+pub fn err_port() -> Port {
+    ERR.with(|v| v.borrow().clone())
+}
+
+/// The port `*in*` is bound to.
+pub fn in_port() -> Port {
+    IN.with(|v| v.borrow().clone())
+}
+
+/// Maximum number of items printed for each collection.
+pub fn print_length() -> Option<usize> {
+    PRINT_LENGTH.with(|v| v.get())
+}
+
+/// Maximum depth of nested collections printed.
+pub fn print_level() -> Option<usize> {
+    PRINT_LEVEL.with(|v| v.get())
+}
+
+/// Prints `value` in full, as `str` and other conversions to strings do.
 pub fn pr_str(value: &MalType, print_readably: bool) -> String {
+    Printer {
+        print_readably,
+        length: None,
+        level: None,
+        depth: 0,
+        atoms: Vec::new(),
+    }
+    .print(value)
+}
+
+/// Prints `value` as output, as `prn` and the REPL do, within
+/// `*print-length*` and `*print-level*`.
+pub fn print_str(value: &MalType, print_readably: bool) -> String {
+    Printer {
+        print_readably,
+        length: print_length(),
+        level: print_level(),
+        depth: 0,
+        atoms: Vec::new(),
+    }
+    .print(value)
+}
+
+/// Realizes the lazy sequences `print_str` would print, so that errors raised by
+/// their thunks can be caught rather than printed.
+pub fn realize_printed(value: &MalType) -> Result<(), MalType> {
+    realize_to(value, print_length(), print_level().unwrap_or(usize::MAX))
+}
+
+fn realize_to(value: &MalType, length: Option<usize>, level: usize) -> Result<(), MalType> {
+    if level == 0 {
+        return Ok(());
+    }
+
+    let length = length.unwrap_or(usize::MAX);
     match value {
-        MalType::True => "true".to_string(),
-        MalType::False => "false".to_string(),
-        MalType::Nil => "nil".to_string(),
-        MalType::Number(number) => format!("{}", number),
-        MalType::Keyword(name) => format!(":{}", name),
-        MalType::Symbol(name) => format!("{}", name),
-        MalType::String(value) => print_string(value, print_readably),
-        MalType::List(list, _) => format!(
-            "({})",
-            list.iter()
-                .map(|value| pr_str(value, print_readably))
-                .collect::<Vec<String>>()
-                .join(" ")
-        ),
-        MalType::Hashmap(value, _) => format!(
-            "{{{}}}",
-            value
-                .iter()
-                .map(|(key, value)| format!(
+        MalType::List(list, _) | MalType::Vector(list, _) => list
+            .iter()
+            .take(length)
+            .try_for_each(|v| realize_to(v, Some(length), level - 1)),
+        MalType::Hashmap(map, _) => map
+            .values()
+            .take(length)
+            .try_for_each(|v| realize_to(v, Some(length), level - 1)),
+        MalType::LazySeq(_) => {
+            let mut current = value.clone();
+            for _ in 0..length {
+                match seq_step(&current)? {
+                    Some((first, rest)) => {
+                        realize_to(&first, Some(length), level - 1)?;
+                        current = rest;
+                    }
+                    None => break,
+                }
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+struct Printer {
+    print_readably: bool,
+    length: Option<usize>,
+    level: Option<usize>,
+    depth: usize,
+    /// Atoms being printed, to detect an atom that contains itself.
+    atoms: Vec<*const RefCell<MalType>>,
+}
+
+impl Printer {
+    fn print(&mut self, value: &MalType) -> String {
+        let print_readably = self.print_readably;
+        match value {
+            MalType::True => "true".to_string(),
+            MalType::False => "false".to_string(),
+            MalType::Nil => "nil".to_string(),
+            MalType::Number(number) => format!("{}", number),
+            MalType::Char(c) => print_char(*c, print_readably),
+            MalType::Keyword(name) => format!(":{}", name),
+            MalType::Symbol(name) => name.to_string(),
+            MalType::String(value) => print_string(value, print_readably),
+            MalType::Regex(regex) if print_readably => format!("#\"{}\"", regex.as_str()),
+            MalType::Regex(regex) => regex.as_str().to_string(),
+            MalType::List(list, _) => self.print_seq("(", list.iter().cloned().map(Ok), ")"),
+            MalType::Hashmap(value, _) => {
+                self.print_seq("{", map_entries(value).into_iter().map(Ok), "}")
+            }
+            MalType::Vector(list, _) => self.print_seq("[", list.iter().cloned().map(Ok), "]"),
+            MalType::Set(set, _) => self.print_seq("#{", set.iter().map(|v| Ok(v.to_mal())), "}"),
+            MalType::Function(_, name, _) => format!("#<fn {}>", name),
+            MalType::Closure(closure, _) => print_closure(closure),
+            MalType::Atom(v) if self.atoms.contains(&Rc::as_ptr(v)) => "#<cycle>".to_string(),
+            MalType::Atom(v) => {
+                self.atoms.push(Rc::as_ptr(v));
+                let value = format!("(atom {})", self.print(&v.borrow()));
+                self.atoms.pop();
+                value
+            }
+            MalType::LazySeq(_) => self.print_seq("(", LazyItems(Some(value.clone())), ")"),
+            MalType::Transducer(_) => "#<transducer>".to_string(),
+            MalType::Reduced(v) => format!("#<reduced {}>", self.print(v)),
+            MalType::Port(port) => match port {
+                Port::Stdout => "#<port stdout>".to_string(),
+                Port::Stderr => "#<port stderr>".to_string(),
+                Port::Stdin => "#<port stdin>".to_string(),
+                Port::Writer(_) => "#<port string-writer>".to_string(),
+                Port::Reader(_) => "#<port string-reader>".to_string(),
+            },
+        }
+    }
+
+    /// Prints the items of a collection, followed by `...` when there are
+    /// more than `*print-length*`. Map entries are printed without brackets.
+    fn print_seq(
+        &mut self,
+        open: &str,
+        items: impl Iterator<Item = Result<MalType, MalType>>,
+        close: &str,
+    ) -> String {
+        if self.level.is_some_and(|level| self.depth >= level) {
+            return "...".to_string();
+        }
+
+        self.depth += 1;
+        let mut values = Vec::new();
+        for (i, item) in items.enumerate() {
+            if self.length.is_some_and(|length| i >= length) {
+                values.push("...".to_string());
+                break;
+            }
+            match item {
+                Ok(MalType::Vector(entry, _)) if open == "{" => values.push(format!(
                     "{} {}",
-                    print_hashable(key, print_readably),
-                    pr_str(value, print_readably)
-                ))
-                .collect::<Vec<String>>()
-                .join(" ")
-        ),
-        MalType::Vector(list, _) => format!(
-            "[{}]",
-            list.iter()
-                .map(|value| pr_str(value, print_readably))
-                .collect::<Vec<String>>()
-                .join(" ")
-        ),
-        MalType::Function(_, _) => format!("#<function>"),
-        MalType::Closure(_, _) => format!("#<function>"),
-        MalType::Atom(v) => format!("(atom {})", pr_str(&v.borrow(), print_readably)),
+                    self.print(&entry[0]),
+                    self.print(&entry[1])
+                )),
+                Ok(item) => values.push(self.print(&item)),
+                Err(err) => {
+                    self.depth -= 1;
+                    return format!("#<error {}>", self.print(&err));
+                }
+            }
+        }
+        self.depth -= 1;
+        format!("{}{}{}", open, values.join(" "), close)
+    }
+}
+
+/// Iterates over a lazy sequence, realizing one element at a time.
+struct LazyItems(Option<MalType>);
+
+impl Iterator for LazyItems {
+    type Item = Result<MalType, MalType>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match seq_step(&self.0.take()?) {
+            Ok(Some((first, rest))) => {
+                self.0 = Some(rest);
+                Some(Ok(first))
+            }
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
     }
 }
 
@@ -49,10 +239,13 @@ impl Display for MalType {
     }
 }
 
-fn print_hashable(hashable: &Hashable, print_readably: bool) -> String {
-    match hashable {
-        Hashable::Keyword(name) => format!(":{}", name),
-        Hashable::String(value) => print_string(value, print_readably),
+fn print_closure(closure: &Closure) -> String {
+    let params = pr_str(&MalType::Vector(closure.params.clone(), None), true);
+    match (closure.is_macro, &closure.name) {
+        (true, Some(name)) => format!("#<macro {}>", name),
+        (true, None) => "#<macro>".to_string(),
+        (false, Some(name)) => format!("#<fn {} {}>", name, params),
+        (false, None) => format!("#<fn {}>", params),
     }
 }
 
@@ -64,13 +257,34 @@ fn print_string(value: &String, print_readably: bool) -> String {
     }
 }
 
+fn print_char(c: char, print_readably: bool) -> String {
+    if !print_readably {
+        return c.to_string();
+    }
+
+    match c {
+        '\n' => "\\newline".to_string(),
+        ' ' => "\\space".to_string(),
+        '\t' => "\\tab".to_string(),
+        '\r' => "\\return".to_string(),
+        '\u{8}' => "\\backspace".to_string(),
+        '\u{c}' => "\\formfeed".to_string(),
+        c if c.is_control() => format!("\\u{:04x}", c as u32),
+        c => format!("\\{}", c),
+    }
+}
+
 fn escape_string(value: &str) -> String {
     value
         .chars()
         .map(|c| match c {
             '\\' => "\\\\".to_string(),
             '\n' => "\\n".to_string(),
+            '\t' => "\\t".to_string(),
+            '\r' => "\\r".to_string(),
+            '\0' => "\\0".to_string(),
             '\"' => "\\\"".to_string(),
+            c if c.is_control() => format!("\\u{:04x}", c as u32),
             c => c.to_string(),
         })
         .collect()
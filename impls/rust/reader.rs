@@ -1,4 +1,7 @@
-use std::collections::{HashMap, VecDeque};
+use std::{cell::RefCell, collections::VecDeque, fmt::Display};
+
+use indexmap::{IndexMap, IndexSet};
+use regex::Regex;
 
 use crate::types::{error, Hashable, MalType};
 
@@ -11,35 +14,107 @@ enum Token {
     LeftBrace,
     RightBrace,
     Number(i64),
+    Char(char),
     Symbol(String),
     Keyword(String),
     String(String),
+    Regex(String),
     Quote,
     Backtick,
     Tilde,
     TildeAt,
     At,
     Caret,
+    /// `#_`, which discards the next form.
+    Discard,
+    /// `#name`, which passes the next form to the reader function of the tag.
+    Tag(String),
+    /// `#{`, which opens a set.
+    SetOpen,
+}
+
+thread_local! {
+    /// The tagged-literal readers `*data-readers*` is bound to.
+    static DATA_READERS: RefCell<IndexMap<Hashable, MalType>> = RefCell::new(IndexMap::new());
+}
+
+/// Updates the tagged-literal readers when `name` is `*data-readers*`.
+/// Anything but a map removes every reader.
+pub fn set_reader_option(name: &str, value: &MalType) {
+    if name != "*data-readers*" {
+        return;
+    }
+    let readers = match value {
+        MalType::Hashmap(readers, _) => readers.clone(),
+        _ => IndexMap::new(),
+    };
+    DATA_READERS.with(|v| *v.borrow_mut() = readers);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
 }
 
 struct Reader {
-    tokens: VecDeque<Token>,
+    tokens: VecDeque<(Token, Position)>,
+    /// Functions reading tagged literals, keyed by tag name, or `None` to
+    /// use those of `*data-readers*` when the literal is read.
+    readers: Option<IndexMap<Hashable, MalType>>,
+    /// Function reading the tagged literals no other reader handles.
+    default_reader: Option<MalType>,
+    /// Whether to read EDN, without the reader macros of code.
+    edn: bool,
 }
 
 impl Reader {
-    pub fn new(tokens: VecDeque<Token>) -> Self {
-        Reader { tokens }
+    pub fn new(tokens: VecDeque<(Token, Position)>) -> Self {
+        Reader {
+            tokens,
+            readers: None,
+            default_reader: None,
+            edn: false,
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.front().map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.tokens.pop_front().map(|(token, _)| token)
+    }
+
+    fn position(&self) -> Option<Position> {
+        self.tokens.front().map(|(_, position)| *position)
+    }
+
+    /// Skips the forms discarded by `#_`.
+    fn skip_discarded(&mut self) -> Result<(), MalType> {
+        while let Some(Token::Discard) = self.peek() {
+            self.next();
+            self.read_form()?;
+        }
+        Ok(())
     }
 
     pub fn read_form(&mut self) -> Result<MalType, MalType> {
-        let token = match self.tokens.front() {
+        self.skip_discarded()?;
+        let token = match self.peek() {
             Some(token) => token,
             None => return error("Unexpected EOF.".to_string()),
         };
 
         match token {
             Token::LeftParen => {
-                self.tokens.pop_front();
+                self.next();
                 self.read_list()
             }
             _ => self.read_atom(),
@@ -50,13 +125,14 @@ impl Reader {
         let mut list = Vec::new();
 
         loop {
-            let token = match self.tokens.front() {
+            self.skip_discarded()?;
+            let token = match self.peek() {
                 Some(token) => token,
                 None => break,
             };
 
             if let Token::RightParen = token {
-                self.tokens.pop_front();
+                self.next();
                 return Ok(MalType::List(list, None));
             }
 
@@ -70,11 +146,37 @@ impl Reader {
     }
 
     fn read_atom(&mut self) -> Result<MalType, MalType> {
-        let token = self.tokens.pop_front().unwrap();
+        let token = self.next().unwrap();
+        if self.edn
+            && matches!(
+                token,
+                Token::Regex(_)
+                    | Token::Quote
+                    | Token::Backtick
+                    | Token::Tilde
+                    | Token::TildeAt
+                    | Token::At
+                    | Token::Caret
+            )
+        {
+            return error(format!("Unexpected token {:?} in EDN.", token));
+        }
+        if let Token::Symbol(name) = &token {
+            // EDN symbols can't start with #, which only begins dispatch
+            // forms like #{} and #_.
+            if self.edn && name.starts_with('#') {
+                return error(format!("Unexpected {} in EDN.", name));
+            }
+        }
+
         match token {
             Token::Number(value) => Ok(MalType::Number(value)),
+            Token::Char(value) => Ok(MalType::Char(value)),
             Token::Symbol(name) => Ok(self.read_symbol(name)),
-            Token::String(value) => Ok(MalType::String(unescape_string(&value))),
+            Token::String(value) => unescape_string(&value).map(MalType::String),
+            Token::Regex(value) => Regex::new(&value)
+                .map(MalType::Regex)
+                .or_else(|err| error(err.to_string())),
             Token::Keyword(name) => Ok(MalType::Keyword(name)),
             Token::LeftBrace => self.read_hashmap(),
             Token::LeftBracket => self.read_vector(),
@@ -100,6 +202,14 @@ impl Reader {
                 .read_form()
                 .and_then(|value| Ok(MalType::List(vec![MalType::symbol("deref"), value], None))),
             Token::Caret => self.read_form().and_then(|second| {
+                // `^:key form` is short for `^{:key true} form`.
+                let second = match second {
+                    MalType::Keyword(key) => MalType::Hashmap(
+                        IndexMap::from([(Hashable::Keyword(key), MalType::True)]),
+                        None,
+                    ),
+                    second => second,
+                };
                 self.read_form().and_then(|first| {
                     Ok(MalType::List(
                         vec![MalType::symbol("with-meta"), first, second],
@@ -107,10 +217,58 @@ impl Reader {
                     ))
                 })
             }),
+            Token::Tag(tag) => self
+                .read_form()
+                .and_then(|value| self.read_tagged(tag, value)),
+            Token::SetOpen => self.read_set(),
             _ => error(format!("Unexpected token {:?}.", token)),
         }
     }
 
+    /// Reads `#tag value` with the reader of the tag, falling back to the
+    /// built-in `#inst` and `#uuid` readers and then to the default reader.
+    fn read_tagged(&self, tag: String, value: MalType) -> Result<MalType, MalType> {
+        let find = |readers: &IndexMap<Hashable, MalType>| {
+            readers
+                .get(&Hashable::String(tag.clone()))
+                .or_else(|| readers.get(&Hashable::Keyword(tag.clone())))
+                .cloned()
+        };
+        let reader = match &self.readers {
+            Some(readers) => find(readers),
+            None => DATA_READERS.with(|v| find(&v.borrow())),
+        };
+        if let Some(reader) = reader {
+            return reader.apply(&vec![value]);
+        }
+
+        match (tag.as_str(), &value) {
+            ("inst", MalType::String(inst)) => {
+                return validate(
+                    "inst",
+                    inst,
+                    r"^\d{4}(-\d{2}(-\d{2}(T\d{2}:\d{2}(:\d{2}(\.\d+)?)?(Z|[+-]\d{2}:\d{2})?)?)?)?$",
+                )
+            }
+            ("uuid", MalType::String(uuid)) => {
+                return validate(
+                    "uuid",
+                    uuid,
+                    r"^[0-9a-fA-F]{8}(-[0-9a-fA-F]{4}){3}-[0-9a-fA-F]{12}$",
+                )
+            }
+            ("inst" | "uuid", value) => {
+                return error(format!("Expected string after #{}, but got {}", tag, value))
+            }
+            _ => {}
+        }
+
+        match &self.default_reader {
+            Some(reader) => reader.apply(&vec![MalType::Symbol(tag), value]),
+            None => error(format!("No reader function for tag {}.", tag)),
+        }
+    }
+
     fn read_symbol(&self, name: String) -> MalType {
         match name.as_str() {
             "true" => MalType::True,
@@ -121,23 +279,22 @@ impl Reader {
     }
 
     fn read_hashmap(&mut self) -> Result<MalType, MalType> {
-        let mut hashmap = HashMap::new();
+        let mut hashmap = IndexMap::new();
 
         loop {
-            let token = match self.tokens.front() {
+            self.skip_discarded()?;
+            let token = match self.peek() {
                 Some(token) => token.clone(),
                 None => break,
             };
 
             if let Token::RightBrace = token {
-                self.tokens.pop_front();
+                self.next();
                 return Ok(MalType::Hashmap(hashmap, None));
             }
 
             let key = match self.read_form() {
-                Ok(MalType::String(value)) => Hashable::String(value),
-                Ok(MalType::Keyword(name)) => Hashable::Keyword(name),
-                Ok(_) => return error(format!("Unexpected token {:?}", token)),
+                Ok(value) => Hashable::new(&value)?,
                 Err(value) => return Err(value),
             };
             match self.read_form() {
@@ -148,17 +305,43 @@ impl Reader {
 
         error("Unexpected EOF.".to_string())
     }
+
+    fn read_set(&mut self) -> Result<MalType, MalType> {
+        let mut set = IndexSet::new();
+
+        loop {
+            self.skip_discarded()?;
+            let token = match self.peek() {
+                Some(token) => token,
+                None => break,
+            };
+
+            if let Token::RightBrace = token {
+                self.next();
+                return Ok(MalType::Set(set, None));
+            }
+
+            let value = self.read_form()?;
+            if !set.insert(Hashable::new(&value)?) {
+                return error(format!("Duplicate set element {}.", value));
+            }
+        }
+
+        error("Unexpected EOF.".to_string())
+    }
+
     fn read_vector(&mut self) -> Result<MalType, MalType> {
         let mut list = Vec::new();
 
         loop {
-            let token = match self.tokens.front() {
+            self.skip_discarded()?;
+            let token = match self.peek() {
                 Some(token) => token,
                 None => break,
             };
 
             if let Token::RightBracket = token {
-                self.tokens.pop_front();
+                self.next();
                 return Ok(MalType::Vector(list, None));
             }
 
@@ -172,6 +355,16 @@ impl Reader {
     }
 }
 
+/// Mal has no date or UUID type, so `#inst` and `#uuid` read as strings
+/// once their format is checked.
+fn validate(tag: &str, value: &str, pattern: &str) -> Result<MalType, MalType> {
+    if Regex::new(pattern).unwrap().is_match(value) {
+        Ok(MalType::String(value.to_string()))
+    } else {
+        error(format!("Invalid #{} \"{}\".", tag, value))
+    }
+}
+
 pub fn read_str(string: &str) -> Result<MalType, MalType> {
     let tokens = tokenize(string);
     match tokens {
@@ -179,15 +372,89 @@ pub fn read_str(string: &str) -> Result<MalType, MalType> {
             let mut reader = Reader::new(tokens);
             reader.read_form()
         }
-        Err(message) => error(message),
+        Err((message, _)) => error(message),
+    }
+}
+
+/// Reads the first form of `string` as EDN, so without evaluation or the
+/// reader macros of code, or returns nil when there is none. Tagged literals
+/// are read by `readers`, then by the built-in readers, then by
+/// `default_reader`.
+pub fn read_edn(
+    string: &str,
+    readers: IndexMap<Hashable, MalType>,
+    default_reader: Option<MalType>,
+) -> Result<MalType, MalType> {
+    let tokens = tokenize(string).or_else(|(message, _)| error(message))?;
+    let mut reader = Reader {
+        tokens,
+        readers: Some(readers),
+        default_reader,
+        edn: true,
+    };
+    reader.skip_discarded()?;
+    if reader.peek().is_none() {
+        return Ok(MalType::Nil);
     }
+    reader.read_form()
 }
 
-fn tokenize(s: &str) -> Result<VecDeque<Token>, String> {
+/// Reads every top-level form of `string`, in order.
+pub fn read_all(string: &str) -> Result<Vec<MalType>, MalType> {
+    read_forms(string).map(|(_, form)| form).collect()
+}
+
+/// Returns an iterator over the top-level forms of `string`, each paired with
+/// the position it starts at. Iteration stops after the first error.
+pub fn read_forms(string: &str) -> Forms {
+    match tokenize(string) {
+        Ok(tokens) => Forms {
+            reader: Reader::new(tokens),
+            error: None,
+        },
+        Err((message, position)) => Forms {
+            reader: Reader::new(VecDeque::new()),
+            error: Some((position, MalType::String(message))),
+        },
+    }
+}
+
+pub struct Forms {
+    reader: Reader,
+    error: Option<(Position, MalType)>,
+}
+
+impl Iterator for Forms {
+    type Item = (Position, Result<MalType, MalType>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((position, value)) = self.error.take() {
+            return Some((position, Err(value)));
+        }
+
+        if let Err(value) = self.reader.skip_discarded() {
+            let position = self.reader.position()?;
+            self.reader.tokens.clear();
+            return Some((position, Err(value)));
+        }
+        let position = self.reader.position()?;
+        let form = self.reader.read_form();
+        if form.is_err() {
+            self.reader.tokens.clear();
+        }
+        Some((position, form))
+    }
+}
+
+fn tokenize(s: &str) -> Result<VecDeque<(Token, Position)>, (String, Position)> {
+    let positions = positions(s);
     let mut chars: VecDeque<char> = s.chars().collect();
     let mut tokens = VecDeque::new();
-    let mut is_comment = false;
+    // A `#!` line starting a script is skipped like a comment.
+    let mut is_comment = s.starts_with("#!");
     while let Some(c) = chars.pop_front() {
+        let position = positions[positions.len() - chars.len() - 1];
+
         if c == '\n' {
             is_comment = false;
             continue;
@@ -226,30 +493,74 @@ fn tokenize(s: &str) -> Result<VecDeque<Token>, String> {
             '@' => Token::At,
             '^' => Token::Caret,
             '\"' => match string(&mut chars) {
-                Ok(token) => token,
-                Err(message) => return Err(message),
+                Ok(value) => Token::String(value),
+                Err(message) => return Err((message, position)),
             },
             '-' => match chars.front() {
                 Some(c) if c.is_numeric() => number(true, chars.pop_front().unwrap(), &mut chars),
                 _ => symbol(c, &mut chars),
             },
+            '\\' => match character(&mut chars) {
+                Ok(token) => token,
+                Err(message) => return Err((message, position)),
+            },
+            '#' if chars.front() == Some(&'_') => {
+                chars.pop_front();
+                Token::Discard
+            }
+            '#' if chars.front() == Some(&'{') => {
+                chars.pop_front();
+                Token::SetOpen
+            }
+            '#' if chars.front().is_some_and(|c| c.is_alphabetic()) => {
+                match symbol(chars.pop_front().unwrap(), &mut chars) {
+                    Token::Symbol(name) => Token::Tag(name),
+                    _ => unreachable!(),
+                }
+            }
+            '#' if chars.front() == Some(&'\"') => {
+                chars.pop_front();
+                match string(&mut chars) {
+                    Ok(value) => Token::Regex(value),
+                    Err(message) => return Err((message, position)),
+                }
+            }
             ':' => keyword(&mut chars),
             c if c.is_numeric() => number(false, c, &mut chars),
             c => symbol(c, &mut chars),
         };
-        tokens.push_back(token);
+        tokens.push_back((token, position));
     }
     Ok(tokens)
 }
 
-fn string(chars: &mut VecDeque<char>) -> Result<Token, String> {
+fn positions(s: &str) -> Vec<Position> {
+    let mut positions = Vec::new();
+    let mut position = Position { line: 1, column: 1 };
+    for c in s.chars() {
+        positions.push(position);
+        if c == '\n' {
+            position = Position {
+                line: position.line + 1,
+                column: 1,
+            };
+        } else {
+            position.column += 1;
+        }
+    }
+    positions
+}
+
+fn string(chars: &mut VecDeque<char>) -> Result<String, String> {
     let mut string = Vec::new();
     while chars.front().is_some() && *chars.front().unwrap() != '\"' {
         let c = chars.pop_front().unwrap();
         string.push(c);
         if c == '\\' {
-            let c = chars.pop_front().unwrap();
-            string.push(c);
+            match chars.pop_front() {
+                Some(c) => string.push(c),
+                None => break,
+            }
         }
     }
 
@@ -258,7 +569,44 @@ fn string(chars: &mut VecDeque<char>) -> Result<Token, String> {
     }
 
     chars.pop_front();
-    Ok(Token::String(string.iter().collect()))
+    Ok(string.iter().collect())
+}
+
+fn character(chars: &mut VecDeque<char>) -> Result<Token, String> {
+    let first = match chars.pop_front() {
+        Some(c) => c,
+        None => return Err("Unexpected EOF.".to_string()),
+    };
+
+    let mut name = vec![first];
+    while let Some(c) = chars.front() {
+        if c.is_whitespace() || is_special_char(*c) || ",;\"".contains(*c) {
+            break;
+        }
+
+        name.push(*c);
+        chars.pop_front();
+    }
+
+    if name.len() == 1 {
+        return Ok(Token::Char(first));
+    }
+
+    let name: String = name.iter().collect();
+    let c = match name.as_str() {
+        "newline" => '\n',
+        "space" => ' ',
+        "tab" => '\t',
+        "return" => '\r',
+        "backspace" => '\u{8}',
+        "formfeed" => '\u{c}',
+        _ if name.len() == 5 && name.starts_with('u') => u32::from_str_radix(&name[1..], 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("Invalid character \\{}.", name))?,
+        _ => return Err(format!("Unsupported character \\{}.", name)),
+    };
+    Ok(Token::Char(c))
 }
 
 fn number(negative: bool, c: char, chars: &mut VecDeque<char>) -> Token {
@@ -310,20 +658,55 @@ fn is_special_char(c: char) -> bool {
     "[]{}()'`~^@".contains(c)
 }
 
-fn unescape_string(value: &str) -> String {
-    let mut result = Vec::new();
-    let mut chars = value.chars();
-    let mut is_escaped = false;
+fn unescape_string(value: &str) -> Result<String, MalType> {
+    let mut result = String::new();
+    let mut chars = value.chars().peekable();
     while let Some(c) = chars.next() {
-        if c == '\\' && !is_escaped {
-            is_escaped = true;
-        } else if is_escaped && c == 'n' {
-            result.push('\n');
-            is_escaped = false;
-        } else {
+        if c != '\\' {
             result.push(c);
-            is_escaped = false;
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some('u') if chars.peek() == Some(&'{') => {
+                chars.next();
+                let mut digits = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => digits.push(c),
+                        None => return error("Unterminated \\u{...} escape.".to_string()),
+                    }
+                }
+                result.push(unicode_escape(&digits)?);
+            }
+            Some('u') => {
+                let digits: String = chars.by_ref().take(4).collect();
+                if digits.chars().count() != 4 {
+                    return error(format!("Invalid unicode escape \\u{}.", digits));
+                }
+                result.push(unicode_escape(&digits)?);
+            }
+            Some(c) => return error(format!("Invalid escape sequence \\{}.", c)),
+            None => return error("Unexpected EOF.".to_string()),
         }
     }
-    result.iter().collect()
+    Ok(result)
+}
+
+fn unicode_escape(digits: &str) -> Result<char, MalType> {
+    if digits.is_empty() || digits.len() > 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return error(format!("Invalid unicode escape \\u{{{}}}.", digits));
+    }
+
+    u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| MalType::String(format!("Invalid code point \\u{{{}}}.", digits)))
 }
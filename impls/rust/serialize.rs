@@ -0,0 +1,633 @@
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap, rc::Rc};
+
+use indexmap::{IndexMap, IndexSet};
+use regex::Regex;
+
+use crate::env::Env;
+use crate::types::{
+    error, seq_iter, seq_to_vec, Closure, ClosureFunction, Function, Hashable, MalType, Port, Xform,
+};
+
+/// Prefix of every serialized value, followed by the format version.
+const MAGIC: &[u8] = b"MAL";
+/// Prefix of every image, followed by the format version.
+const IMAGE_MAGIC: &[u8] = b"MALIMAGE";
+pub const VERSION: u8 = 1;
+/// Most items of a lazy sequence realized to encode it, so that encoding an
+/// infinite one fails instead of running out of memory.
+const SEQ_LIMIT: usize = 100_000;
+
+const NIL: u8 = 0;
+const TRUE: u8 = 1;
+const FALSE: u8 = 2;
+const NUMBER: u8 = 3;
+const CHAR: u8 = 4;
+const STRING: u8 = 5;
+const SYMBOL: u8 = 6;
+const KEYWORD: u8 = 7;
+const REGEX: u8 = 8;
+const LIST: u8 = 9;
+const VECTOR: u8 = 10;
+const HASHMAP: u8 = 11;
+// Only found in images.
+const FUNCTION: u8 = 12;
+const CLOSURE: u8 = 13;
+const ATOM: u8 = 14;
+const ENV: u8 = 15;
+const PORT: u8 = 16;
+const TRANSDUCER: u8 = 17;
+// Found in serialized values too, added after the image tags.
+const SET: u8 = 18;
+
+pub fn ns() -> HashMap<&'static str, Function> {
+    let mut ns: HashMap<&'static str, Function> = HashMap::new();
+    ns.insert("serialize", |args| {
+        Ok(MalType::Vector(
+            serialize(&args[0])?
+                .into_iter()
+                .map(|byte| MalType::Number(byte as i64))
+                .collect(),
+            None,
+        ))
+    });
+    ns.insert("deserialize", |args| {
+        let bytes = seq_to_vec(&args[0])?
+            .iter()
+            .map(|byte| match byte {
+                MalType::Number(byte @ 0..=255) => Ok(*byte as u8),
+                value => error(format!("Expected byte, but got {}", value)),
+            })
+            .collect::<Result<Vec<u8>, MalType>>()?;
+        deserialize(&bytes)
+    });
+    ns
+}
+
+/// Arglists and docstrings of the builtins in `ns()`.
+pub fn docs() -> Vec<(&'static str, &'static str, &'static str)> {
+    vec![
+        ("serialize", "([x])", "Encodes x and its metadata as a vector of bytes. Functions, atoms and ports cannot be encoded, nor lazy sequences of more than 100000 items."),
+        ("deserialize", "([bytes])", "Decodes a value encoded by serialize."),
+    ]
+}
+
+/// Encodes `value` in the versioned binary format `deserialize` reads.
+/// Lazy sequences are realized and encoded as lists.
+pub fn serialize(value: &MalType) -> Result<Vec<u8>, MalType> {
+    let mut encoder = Encoder::new(MAGIC, false);
+    encoder.value(value)?;
+    Ok(encoder.bytes)
+}
+
+/// Decodes a value encoded by `serialize`.
+pub fn deserialize(bytes: &[u8]) -> Result<MalType, MalType> {
+    let mut decoder = Decoder::new(bytes, MAGIC, None)?;
+    let value = decoder.value()?;
+    decoder.finish()?;
+    Ok(value)
+}
+
+/// Encodes the global environment `env`, with the closures, atoms and
+/// environments it refers to, and the names of the dynamic vars. Lazy
+/// sequences are realized and saved as lists.
+pub fn save_image(env: &Rc<RefCell<Env>>, dynamic: &[String]) -> Result<Vec<u8>, MalType> {
+    let mut encoder = Encoder::new(IMAGE_MAGIC, true);
+    encoder.env(env)?;
+    encoder.varint(dynamic.len() as u64);
+    for name in dynamic {
+        encoder.text(name);
+    }
+    Ok(encoder.bytes)
+}
+
+/// Decodes an image saved by `save_image`, returning the global environment
+/// and the names of the dynamic vars. Builtins are found by name in
+/// `builtins`, and closures are applied with `closure_fn`.
+pub fn load_image(
+    bytes: &[u8],
+    builtins: HashMap<&'static str, Function>,
+    closure_fn: ClosureFunction,
+) -> Result<(Rc<RefCell<Env>>, Vec<String>), MalType> {
+    let mut decoder = Decoder::new(
+        bytes,
+        IMAGE_MAGIC,
+        Some(Linker {
+            builtins,
+            closure_fn,
+        }),
+    )?;
+    let env = match decoder.env()? {
+        Some(env) => env,
+        None => return decoder.fail("missing environment"),
+    };
+    let mut dynamic = Vec::new();
+    for _ in 0..decoder.length()? {
+        dynamic.push(decoder.text()?);
+    }
+    decoder.finish()?;
+    Ok((env, dynamic))
+}
+
+struct Encoder {
+    bytes: Vec<u8>,
+    /// Whether functions, atoms and ports may be encoded.
+    image: bool,
+    /// Identifiers of the environments and atoms already encoded, so that
+    /// shared and cyclic references are encoded once.
+    envs: HashMap<*const RefCell<Env>, usize>,
+    atoms: HashMap<*const RefCell<MalType>, usize>,
+}
+
+impl Encoder {
+    fn new(magic: &[u8], image: bool) -> Self {
+        let mut bytes = magic.to_vec();
+        bytes.push(VERSION);
+        Encoder {
+            bytes,
+            image,
+            envs: HashMap::new(),
+            atoms: HashMap::new(),
+        }
+    }
+
+    fn value(&mut self, value: &MalType) -> Result<(), MalType> {
+        match value {
+            MalType::Nil => self.bytes.push(NIL),
+            MalType::True => self.bytes.push(TRUE),
+            MalType::False => self.bytes.push(FALSE),
+            MalType::Number(value) => {
+                self.bytes.push(NUMBER);
+                // Zigzag encoding keeps small negative numbers short.
+                self.varint(((value << 1) ^ (value >> 63)) as u64);
+            }
+            MalType::Char(c) => {
+                self.bytes.push(CHAR);
+                self.varint(*c as u64);
+            }
+            MalType::String(value) => self.tagged_text(STRING, value),
+            MalType::Symbol(name) => self.tagged_text(SYMBOL, name),
+            MalType::Keyword(name) => self.tagged_text(KEYWORD, name),
+            MalType::Regex(regex) => self.tagged_text(REGEX, regex.as_str()),
+            MalType::List(items, meta) | MalType::Vector(items, meta) => {
+                self.bytes.push(if let MalType::List(_, _) = value {
+                    LIST
+                } else {
+                    VECTOR
+                });
+                self.metadata(meta)?;
+                self.varint(items.len() as u64);
+                for item in items {
+                    self.value(item)?;
+                }
+            }
+            MalType::LazySeq(_) => {
+                let items = seq_iter(value)
+                    .take(SEQ_LIMIT + 1)
+                    .collect::<Result<Vec<MalType>, MalType>>()?;
+                if items.len() > SEQ_LIMIT {
+                    return error(format!(
+                        "Cannot serialize a lazy sequence of more than {} items",
+                        SEQ_LIMIT
+                    ));
+                }
+                self.value(&MalType::List(items, None))?
+            }
+            MalType::Set(set, meta) => {
+                self.bytes.push(SET);
+                self.metadata(meta)?;
+                self.varint(set.len() as u64);
+                for item in set {
+                    self.value(&item.to_mal())?;
+                }
+            }
+            MalType::Hashmap(map, meta) => {
+                self.bytes.push(HASHMAP);
+                self.metadata(meta)?;
+                self.varint(map.len() as u64);
+                for (key, value) in map {
+                    self.value(&key.to_mal())?;
+                    self.value(value)?;
+                }
+            }
+            MalType::Function(_, name, meta) if self.image => {
+                self.tagged_text(FUNCTION, name);
+                self.metadata(meta)?;
+            }
+            MalType::Closure(closure, meta) if self.image => {
+                self.bytes.push(CLOSURE);
+                self.metadata(meta)?;
+                match &closure.name {
+                    Some(name) => self.tagged_text(STRING, name),
+                    None => self.bytes.push(NIL),
+                }
+                self.bytes.push(closure.is_macro as u8);
+                self.varint(closure.params.len() as u64);
+                for param in &closure.params {
+                    self.value(param)?;
+                }
+                self.value(&closure.body)?;
+                self.env(&closure.env)?;
+            }
+            MalType::Atom(atom) if self.image => {
+                self.bytes.push(ATOM);
+                let next = self.atoms.len();
+                let id = *self.atoms.entry(Rc::as_ptr(atom)).or_insert(next);
+                self.varint(id as u64);
+                if id == next {
+                    self.value(&atom.borrow())?;
+                }
+            }
+            MalType::Port(port) if self.image => {
+                self.bytes.push(PORT);
+                match port {
+                    Port::Stdout => self.bytes.push(0),
+                    Port::Stderr => self.bytes.push(1),
+                    Port::Stdin => self.bytes.push(2),
+                    Port::Writer(buffer) => {
+                        self.bytes.push(3);
+                        self.text(&buffer.borrow());
+                    }
+                    Port::Reader(input) => {
+                        self.bytes.push(4);
+                        self.text(&input.borrow());
+                    }
+                }
+            }
+            MalType::Transducer(xforms) if self.image => {
+                self.bytes.push(TRANSDUCER);
+                self.varint(xforms.len() as u64);
+                for xform in xforms.iter() {
+                    self.xform(xform)?;
+                }
+            }
+            value => return error(format!("Cannot serialize {}", value)),
+        }
+        Ok(())
+    }
+
+    fn xform(&mut self, xform: &Xform) -> Result<(), MalType> {
+        match xform {
+            Xform::Map(f) => {
+                self.bytes.push(0);
+                self.value(f)
+            }
+            Xform::Filter(pred, keep) => {
+                self.bytes.push(1);
+                self.bytes.push(*keep as u8);
+                self.value(pred)
+            }
+            Xform::Take(n) => {
+                self.bytes.push(2);
+                self.value(&MalType::Number(*n))
+            }
+            Xform::Drop(n) => {
+                self.bytes.push(3);
+                self.value(&MalType::Number(*n))
+            }
+            Xform::TakeWhile(pred) => {
+                self.bytes.push(4);
+                self.value(pred)
+            }
+            Xform::Custom(step, complete) => {
+                self.bytes.push(5);
+                self.value(step)?;
+                self.value(complete.as_ref().unwrap_or(&MalType::Nil))
+            }
+        }
+    }
+
+    /// Encodes an environment the first time it is found, with its outer
+    /// environments, and a reference to it afterwards.
+    fn env(&mut self, env: &Rc<RefCell<Env>>) -> Result<(), MalType> {
+        self.bytes.push(ENV);
+        let next = self.envs.len();
+        let id = *self.envs.entry(Rc::as_ptr(env)).or_insert(next);
+        self.varint(id as u64);
+        if id != next {
+            return Ok(());
+        }
+
+        match &env.borrow().outer {
+            Some(outer) => self.env(outer)?,
+            None => self.bytes.push(NIL),
+        }
+        let mut names = env.borrow().symbols();
+        names.sort();
+        self.varint(names.len() as u64);
+        for name in names {
+            self.text(&name);
+            let value = env.borrow().get(&name).unwrap();
+            self.value(&value).map_err(|err| match err {
+                MalType::String(message) => MalType::String(format!("{}: {}", name, message)),
+                err => err,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn metadata(&mut self, meta: &Option<Box<MalType>>) -> Result<(), MalType> {
+        match meta {
+            Some(meta) => self.value(meta),
+            None => self.value(&MalType::Nil),
+        }
+    }
+
+    fn tagged_text(&mut self, tag: u8, value: &str) {
+        self.bytes.push(tag);
+        self.text(value);
+    }
+
+    fn text(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    /// Appends `value` in LEB128, seven bits per byte.
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+}
+
+/// What an image needs to recreate builtins and closures.
+struct Linker {
+    builtins: HashMap<&'static str, Function>,
+    closure_fn: ClosureFunction,
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    linker: Option<Linker>,
+    envs: Vec<Rc<RefCell<Env>>>,
+    atoms: Vec<Rc<RefCell<MalType>>>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8], magic: &[u8], linker: Option<Linker>) -> Result<Self, MalType> {
+        let header = magic.len() + 1;
+        if bytes.len() < header || &bytes[..magic.len()] != magic {
+            return error("Invalid serialized data: missing header".to_string());
+        }
+        if bytes[magic.len()] != VERSION {
+            return error(format!(
+                "Unsupported serialization format version {}",
+                bytes[magic.len()]
+            ));
+        }
+
+        Ok(Decoder {
+            bytes,
+            position: header,
+            linker,
+            envs: Vec::new(),
+            atoms: Vec::new(),
+        })
+    }
+
+    fn finish(&self) -> Result<(), MalType> {
+        if self.position != self.bytes.len() {
+            return self.fail("trailing bytes");
+        }
+        Ok(())
+    }
+    fn fail<T>(&self, message: &str) -> Result<T, MalType> {
+        error(format!(
+            "Invalid serialized data at byte {}: {}",
+            self.position, message
+        ))
+    }
+
+    fn byte(&mut self) -> Result<u8, MalType> {
+        match self.bytes.get(self.position) {
+            Some(byte) => {
+                self.position += 1;
+                Ok(*byte)
+            }
+            None => self.fail("unexpected end of data"),
+        }
+    }
+
+    fn varint(&mut self) -> Result<u64, MalType> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        self.fail("varint too long")
+    }
+
+    fn length(&mut self) -> Result<usize, MalType> {
+        let length = self.varint()? as usize;
+        if length > self.bytes.len() - self.position {
+            return self.fail("length past the end of data");
+        }
+        Ok(length)
+    }
+
+    fn text(&mut self) -> Result<String, MalType> {
+        let length = self.length()?;
+        let bytes = &self.bytes[self.position..self.position + length];
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(text) => {
+                self.position += length;
+                Ok(text)
+            }
+            Err(_) => self.fail("invalid UTF-8"),
+        }
+    }
+
+    fn metadata(&mut self) -> Result<Option<Box<MalType>>, MalType> {
+        Ok(match self.value()? {
+            MalType::Nil => None,
+            meta => Some(Box::new(meta)),
+        })
+    }
+
+    fn value(&mut self) -> Result<MalType, MalType> {
+        let tag = self.byte()?;
+        Ok(match tag {
+            NIL => MalType::Nil,
+            TRUE => MalType::True,
+            FALSE => MalType::False,
+            NUMBER => {
+                let value = self.varint()?;
+                MalType::Number((value >> 1) as i64 ^ -((value & 1) as i64))
+            }
+            CHAR => match char::from_u32(self.varint()? as u32) {
+                Some(c) => MalType::Char(c),
+                None => return self.fail("invalid character"),
+            },
+            STRING => MalType::String(self.text()?),
+            SYMBOL => MalType::Symbol(self.text()?),
+            KEYWORD => MalType::Keyword(self.text()?),
+            REGEX => match Regex::new(&self.text()?) {
+                Ok(regex) => MalType::Regex(regex),
+                Err(_) => return self.fail("invalid regex"),
+            },
+            LIST | VECTOR => {
+                let meta = self.metadata()?;
+                let length = self.length()?;
+                let mut items = Vec::with_capacity(length);
+                for _ in 0..length {
+                    items.push(self.value()?);
+                }
+                if tag == LIST {
+                    MalType::List(items, meta)
+                } else {
+                    MalType::Vector(items, meta)
+                }
+            }
+            HASHMAP => {
+                let meta = self.metadata()?;
+                let length = self.length()?;
+                let mut map = IndexMap::with_capacity(length);
+                for _ in 0..length {
+                    let key = match Hashable::new(&self.value()?) {
+                        Ok(key) => key,
+                        Err(_) => return self.fail("invalid hashmap key"),
+                    };
+                    map.insert(key, self.value()?);
+                }
+                MalType::Hashmap(map, meta)
+            }
+            SET => {
+                let meta = self.metadata()?;
+                let length = self.length()?;
+                let mut set = IndexSet::with_capacity(length);
+                for _ in 0..length {
+                    match Hashable::new(&self.value()?) {
+                        Ok(item) => set.insert(item),
+                        Err(_) => return self.fail("invalid set element"),
+                    };
+                }
+                MalType::Set(set, meta)
+            }
+            FUNCTION | CLOSURE | ATOM | PORT | TRANSDUCER if self.linker.is_none() => {
+                return self.fail("image data in a serialized value")
+            }
+            FUNCTION => {
+                let name = self.text()?;
+                let meta = self.metadata()?;
+                let linker = self.linker.as_ref().unwrap();
+                match linker.builtins.get_key_value(name.as_str()) {
+                    Some((name, function)) => MalType::Function(*function, name, meta),
+                    None => return self.fail(&format!("unknown builtin {}", name)),
+                }
+            }
+            CLOSURE => {
+                let meta = self.metadata()?;
+                let name = match self.value()? {
+                    MalType::String(name) => Some(name),
+                    _ => None,
+                };
+                let is_macro = self.byte()? != 0;
+                let mut params = Vec::new();
+                for _ in 0..self.length()? {
+                    params.push(self.value()?);
+                }
+                let body = self.value()?;
+                let env = match self.env()? {
+                    Some(env) => env,
+                    None => return self.fail("closure without environment"),
+                };
+                let mut closure =
+                    Closure::new(params, body, env, self.linker.as_ref().unwrap().closure_fn);
+                closure.is_macro = is_macro;
+                closure.name = name;
+                MalType::Closure(Box::new(closure), meta)
+            }
+            ATOM => {
+                let id = self.varint()? as usize;
+                match id.cmp(&self.atoms.len()) {
+                    Ordering::Less => MalType::Atom(self.atoms[id].clone()),
+                    Ordering::Equal => {
+                        let atom = Rc::new(RefCell::new(MalType::Nil));
+                        self.atoms.push(atom.clone());
+                        *atom.borrow_mut() = self.value()?;
+                        MalType::Atom(atom)
+                    }
+                    Ordering::Greater => return self.fail("invalid atom reference"),
+                }
+            }
+            PORT => MalType::Port(match self.byte()? {
+                0 => Port::Stdout,
+                1 => Port::Stderr,
+                2 => Port::Stdin,
+                3 => Port::Writer(Rc::new(RefCell::new(self.text()?))),
+                4 => Port::Reader(Rc::new(RefCell::new(self.text()?))),
+                _ => return self.fail("unknown port"),
+            }),
+            TRANSDUCER => {
+                let mut xforms = Vec::new();
+                for _ in 0..self.length()? {
+                    xforms.push(self.xform()?);
+                }
+                MalType::Transducer(Rc::new(xforms))
+            }
+            _ => return self.fail(&format!("unknown tag {}", tag)),
+        })
+    }
+
+    fn xform(&mut self) -> Result<Xform, MalType> {
+        Ok(match self.byte()? {
+            0 => Xform::Map(self.value()?),
+            1 => {
+                let keep = self.byte()? != 0;
+                Xform::Filter(self.value()?, keep)
+            }
+            2 => Xform::Take(self.count()?),
+            3 => Xform::Drop(self.count()?),
+            4 => Xform::TakeWhile(self.value()?),
+            5 => {
+                let step = self.value()?;
+                let complete = match self.value()? {
+                    MalType::Nil => None,
+                    complete => Some(complete),
+                };
+                Xform::Custom(step, complete)
+            }
+            _ => return self.fail("unknown transducer stage"),
+        })
+    }
+
+    fn count(&mut self) -> Result<i64, MalType> {
+        match self.value()? {
+            MalType::Number(n) => Ok(n),
+            _ => self.fail("invalid transducer count"),
+        }
+    }
+
+    /// Decodes an environment, or `None` for a missing one.
+    fn env(&mut self) -> Result<Option<Rc<RefCell<Env>>>, MalType> {
+        match self.byte()? {
+            NIL => return Ok(None),
+            ENV => {}
+            _ => return self.fail("expected environment"),
+        }
+        let id = self.varint()? as usize;
+        match id.cmp(&self.envs.len()) {
+            Ordering::Less => return Ok(Some(self.envs[id].clone())),
+            Ordering::Equal => {}
+            Ordering::Greater => return self.fail("invalid environment reference"),
+        }
+
+        // The identifier is taken before the outer environment is encoded.
+        let env = Rc::new(RefCell::new(Env::new(None)));
+        self.envs.push(env.clone());
+        let outer = self.env()?;
+        env.borrow_mut().outer = outer;
+        for _ in 0..self.length()? {
+            let name = self.text()?;
+            let value = self.value()?;
+            env.borrow_mut().set(name, value);
+        }
+        Ok(Some(env))
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use indexmap::IndexMap;
 use printer::pr_str;
 use reader::read_str;
 use rustyline::Editor;
@@ -72,7 +73,7 @@ fn eval(ast: &MalType, repl_env: &ReplEnv) -> Result<MalType, MalType> {
             };
 
             match &result[0] {
-                MalType::Function(f, _) => f(&result[1..].to_vec()),
+                MalType::Function(f, _, _) => f(&result[1..].to_vec()),
                 value => error(format!("Unexpected value {}.", pr_str(&value, true))),
             }
         }
@@ -83,9 +84,9 @@ fn eval(ast: &MalType, repl_env: &ReplEnv) -> Result<MalType, MalType> {
 fn eval_ast(ast: &MalType, repl_env: &ReplEnv) -> Result<MalType, MalType> {
     match ast {
         MalType::Symbol(name) => repl_env
-            .get(name.as_str())
+            .get_key_value(name.as_str())
             .ok_or(MalType::String(format!("Undefined symbol {}.", name)))
-            .map(|f| MalType::Function(*f, None)),
+            .map(|(name, f)| MalType::Function(*f, name, None)),
         MalType::List(list, metadata) => {
             let mut result = Vec::new();
             for value in list {
@@ -107,7 +108,7 @@ fn eval_ast(ast: &MalType, repl_env: &ReplEnv) -> Result<MalType, MalType> {
             Ok(MalType::Vector(result, metadata.clone()))
         }
         MalType::Hashmap(map, metadata) => {
-            let mut result = HashMap::new();
+            let mut result = IndexMap::new();
             for (key, value) in map {
                 result.insert(
                     key.clone(),
@@ -1,6 +1,7 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
 use env::Env;
+use indexmap::IndexMap;
 use printer::pr_str;
 use reader::read_str;
 use rustyline::Editor;
@@ -26,19 +27,19 @@ fn main() {
     let mut env: Env = Env::new(None);
     env.set(
         "+".to_string(),
-        MalType::Function(|args| binary_op(args, |a, b| a + b), None),
+        MalType::Function(|args| binary_op(args, |a, b| a + b), "+", None),
     );
     env.set(
         "-".to_string(),
-        MalType::Function(|args| binary_op(args, |a, b| a - b), None),
+        MalType::Function(|args| binary_op(args, |a, b| a - b), "-", None),
     );
     env.set(
         "*".to_string(),
-        MalType::Function(|args| binary_op(args, |a, b| a * b), None),
+        MalType::Function(|args| binary_op(args, |a, b| a * b), "*", None),
     );
     env.set(
         "/".to_string(),
-        MalType::Function(|args| binary_op(args, |a, b| a / b), None),
+        MalType::Function(|args| binary_op(args, |a, b| a / b), "/", None),
     );
     let env = Rc::new(RefCell::new(env));
 
@@ -127,7 +128,7 @@ fn eval_ast(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
             Ok(MalType::Vector(result, metadata.clone()))
         }
         MalType::Hashmap(map, metadata) => {
-            let mut result = HashMap::new();
+            let mut result = IndexMap::new();
             for (key, value) in map {
                 result.insert(key.clone(), eval(value, &env)?);
             }
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
 mod core;
 mod env;
@@ -8,6 +8,7 @@ mod types;
 
 use crate::core::ns;
 use env::Env;
+use indexmap::IndexMap;
 use printer::pr_str;
 use reader::read_str;
 use rustyline::Editor;
@@ -29,7 +30,10 @@ fn main() {
 fn create_env() -> Rc<RefCell<Env>> {
     let mut env: Env = Env::new(None);
     for (symbol, function) in ns() {
-        env.set(symbol.to_string(), MalType::Function(function, None));
+        env.set(
+            symbol.to_string(),
+            MalType::Function(function, symbol, None),
+        );
     }
     Rc::new(RefCell::new(env))
 }
@@ -184,7 +188,9 @@ fn eval(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
                                     &list[1..].to_vec(),
                                 )));
                             }
-                            MalType::Function(function, _) => return function(&list[1..].to_vec()),
+                            MalType::Function(function, _, _) => {
+                                return function(&list[1..].to_vec())
+                            }
                             _ => return error(format!("Expected function but got {}", &list[0])),
                         }
                     }
@@ -217,7 +223,7 @@ fn eval_ast(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
             Ok(MalType::Vector(result, metadata.clone()))
         }
         MalType::Hashmap(map, metadata) => {
-            let mut result = HashMap::new();
+            let mut result = IndexMap::new();
             for (key, value) in map {
                 result.insert(key.clone(), eval(value, &env)?);
             }
@@ -1,75 +1,596 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
+mod collection;
 mod core;
+mod dynamic;
 mod env;
+mod file;
+mod json;
+mod pprint;
 mod printer;
 mod reader;
+mod serialize;
+mod string;
+mod system;
 mod types;
 
-use crate::core::ns;
+use crate::core::{builtin_doc, ns, register_docs};
+use dynamic::{declare_dynamic, dynamic_vars, is_bound, is_dynamic, pop_bindings, push_bindings};
 use env::Env;
-use printer::pr_str;
-use reader::read_str;
+use indexmap::IndexMap;
+use printer::{out_port, pr_str, print_str, realize_printed, set_port, set_print_option};
+use reader::{read_forms, read_str, set_reader_option, Position};
 use rustyline::Editor;
-use types::{error, Closure, MalType};
+use serialize::{load_image, save_image};
+use types::{error, Closure, Function, Hashable, MalType, Port};
+
+thread_local! {
+    /// The global environment, for the builtins that evaluate code or look
+    /// up vars.
+    static GLOBAL_ENV: RefCell<Option<Rc<RefCell<Env>>>> = const { RefCell::new(None) };
+}
+
+const PRELUDE: &str = include_str!("prelude.mal");
+const TESTING: &str = include_str!("testing.mal");
+
+const USAGE: &str = "Usage: mal [options] [file | -] [args...]
+       mal [options] test [paths...]
+
+Runs file, or the script read from standard input when it is -, with args
+as *ARGV*. Then calls its -main function, if it defines one, with args and
+exits with the number it returns. Starts a REPL when there is nothing to run.
+
+The test command runs the tests of each file in paths, and of the files
+named *_test.mal or *-test.mal in their directories, with run-tests. Paths
+default to the current directory. Run a script named test as -- test or
+./test.
+
+Options:
+  -e, --eval EXPR    Evaluate EXPR and print its result unless it is nil
+  -l, --load FILE    Load FILE before running the script
+  -i, --interactive  Start a REPL after running everything else
+  --image FILE       Start from an image saved by save-image
+  --no-prelude       Don't load the prelude or the testing library
+  --version          Print the version and exit
+  -h, --help         Print this help and exit";
+
+/// An option run, in command line order, before the script.
+enum Action {
+    Eval(String),
+    Load(String),
+}
+
+struct Options {
+    actions: Vec<Action>,
+    interactive: bool,
+    image: Option<String>,
+    prelude: bool,
+    /// The script to run, where `-` is standard input.
+    script: Option<String>,
+    argv: Vec<String>,
+    /// The paths given to the test command.
+    tests: Option<Vec<String>>,
+}
 
 fn main() {
-    let env = create_env();
-    let args: Vec<String> = std::env::args().collect();
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            exit(2);
+        }
+    };
 
-    load_utils(env.clone(), &args);
+    if let Some(paths) = &options.tests {
+        exit(run_tests(&options, paths));
+    }
 
-    if args.len() > 1 {
-        run_file(&env, &args[1]);
-    } else {
+    let env = start(&options);
+    let mut code = 0;
+    if let Some(script) = &options.script {
+        code = run_file(&env, script, &options.argv).unwrap_or_else(|message| {
+            eprintln!("Error: {}", message);
+            exit(1);
+        });
+    }
+
+    if options.interactive || (options.script.is_none() && options.actions.is_empty()) {
         repl(&env);
     }
+    exit(code);
+}
+
+/// Creates the global environment and runs the `-e` and `--load` options in
+/// it, exiting if one throws.
+fn start(options: &Options) -> Rc<RefCell<Env>> {
+    let env = match &options.image {
+        Some(filename) => load_image_file(filename).unwrap_or_else(|err| {
+            eprintln!("Error: {}", print(&err));
+            exit(1);
+        }),
+        None => {
+            let env = create_env();
+            load_utils(env.clone(), options.prelude);
+            env
+        }
+    };
+    set_argv(&env, &options.argv);
+
+    for action in &options.actions {
+        let result = match action {
+            Action::Eval(expr) => rep(expr, &env).map(|result| {
+                if result != "nil" {
+                    println!("{}", result);
+                }
+            }),
+            Action::Load(filename) => load_file(filename, &env)
+                .map(|_| ())
+                .map_err(|err| print(&err)),
+        };
+        if let Err(message) = result {
+            eprintln!("Error: {}", message);
+            exit(1);
+        }
+    }
+    env
+}
+
+/// Parses the options before the script; every argument after the script
+/// goes to `*ARGV*`. Exits for `--help` and `--version`.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        actions: Vec::new(),
+        interactive: false,
+        image: None,
+        prelude: true,
+        script: None,
+        argv: Vec::new(),
+        tests: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for option {}", arg))
+        };
+        match arg.as_str() {
+            "-e" | "--eval" => options.actions.push(Action::Eval(value()?)),
+            "-l" | "--load" => options.actions.push(Action::Load(value()?)),
+            "--image" => options.image = Some(value()?),
+            "-i" | "--interactive" => options.interactive = true,
+            "--no-prelude" => options.prelude = false,
+            "--version" => {
+                println!("mal {} [rust]", env!("CARGO_PKG_VERSION"));
+                exit(0);
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "--" => {
+                options.script = args.next();
+                break;
+            }
+            "test" => {
+                options.tests = Some(args.by_ref().collect());
+                break;
+            }
+            option if option.starts_with('-') && option != "-" => {
+                return Err(format!("Unknown option {}", option));
+            }
+            _ => {
+                options.script = Some(arg);
+                break;
+            }
+        }
+    }
+    options.argv = args.collect();
+    Ok(options)
+}
+
+/// Exits with `code` once buffered output is written.
+fn exit(code: i32) -> ! {
+    let _ = io::stdout().flush();
+    std::process::exit(code)
 }
 
 fn create_env() -> Rc<RefCell<Env>> {
     let mut env: Env = Env::new(None);
-    for (symbol, function) in ns() {
-        env.set(symbol.to_string(), MalType::Function(function, None));
+    register_all_docs();
+    for (symbol, function) in builtins() {
+        env.set(
+            symbol.to_string(),
+            MalType::Function(function, symbol, None),
+        );
     }
-    Rc::new(RefCell::new(env))
+    let env = Rc::new(RefCell::new(env));
+    set_global_env(&env);
+    env
+}
+
+fn set_global_env(env: &Rc<RefCell<Env>>) {
+    GLOBAL_ENV.with(|global| *global.borrow_mut() = Some(env.clone()));
+}
+
+fn global_env() -> Rc<RefCell<Env>> {
+    GLOBAL_ENV.with(|global| {
+        global
+            .borrow()
+            .clone()
+            .expect("the global environment exists")
+    })
+}
+
+fn register_all_docs() {
+    register_docs(
+        core::docs()
+            .into_iter()
+            .chain(string::docs())
+            .chain(collection::docs())
+            .chain(file::docs())
+            .chain(json::docs())
+            .chain(serialize::docs())
+            .chain(system::docs())
+            .chain(pprint::docs())
+            .chain(env_docs()),
+    );
 }
 
-fn load_utils(env: Rc<RefCell<Env>>, args: &Vec<String>) {
+fn builtins() -> HashMap<&'static str, Function> {
+    ns().into_iter()
+        .chain(string::ns())
+        .chain(collection::ns())
+        .chain(file::ns())
+        .chain(json::ns())
+        .chain(serialize::ns())
+        .chain(system::ns())
+        .chain(pprint::ns())
+        .chain(env_ns())
+        .collect()
+}
+
+/// The builtins working on the global environment.
+fn env_ns() -> HashMap<&'static str, Function> {
+    let mut ns: HashMap<&'static str, Function> = HashMap::new();
+    ns.insert("load-file", |args| {
+        load_file(args[0].as_string()?, &global_env())
+    });
+    ns.insert("doc", |args| {
+        let (name, value) = match &args[0] {
+            MalType::Symbol(name) => match global_env().borrow().get(name) {
+                Some(value) => (name.clone(), value),
+                None => return error(format!("'{}' not found", name)),
+            },
+            MalType::Function(_, name, _) => (name.to_string(), args[0].clone()),
+            MalType::Closure(closure, _) => (
+                closure.name.clone().unwrap_or_else(|| print(&args[0])),
+                args[0].clone(),
+            ),
+            value => (print(value), value.clone()),
+        };
+        out_port().write(&format!("{}\n", doc_string(&name, &value)))?;
+        Ok(MalType::Nil)
+    });
+    ns.insert("find-doc", |args| {
+        let env = global_env();
+        let mut symbols = env.borrow().symbols();
+        symbols.sort();
+        for name in symbols {
+            let value = env.borrow().get(&name).unwrap_or(MalType::Nil);
+            let (_, doc) = documentation(&value);
+            if matches(&args[0], &name)? || matches(&args[0], &doc.unwrap_or_default())? {
+                out_port().write(&format!("{}\n", doc_string(&name, &value)))?;
+            }
+        }
+        Ok(MalType::Nil)
+    });
+    ns.insert("apropos", |args| {
+        let mut symbols = global_env().borrow().symbols();
+        symbols.sort();
+        let mut result = Vec::new();
+        for name in symbols {
+            if matches(&args[0], &name)? {
+                result.push(MalType::Symbol(name));
+            }
+        }
+        Ok(MalType::List(result, None))
+    });
+    ns.insert("save-image", |args| {
+        let filename = args[0].as_string()?;
+        let bytes = save_image(&global_env(), &dynamic_vars())?;
+        fs::write(filename, bytes).or_else(|err| error(format!("{}: {}", filename, err)))?;
+        Ok(MalType::Nil)
+    });
+    ns
+}
+
+fn env_docs() -> Vec<(&'static str, &'static str, &'static str)> {
+    vec![(
+        "doc",
+        "([x])",
+        "Prints the name, arglists and docstring of x, or of the global var named by x when it is a symbol.",
+    ), (
+        "find-doc",
+        "([pattern])",
+        "Prints the docs of the global vars whose name or docstring contains the string pattern, or matches it when it is a regex.",
+    ), (
+        "apropos",
+        "([pattern])",
+        "Returns a sorted list of the global vars whose name contains the string pattern, or matches it when it is a regex.",
+    ), (
+        "load-file",
+        "([filename])",
+        "Evaluates the forms of the file filename in the global environment. Errors are prefixed with their location; thrown maps get :file, :line, :column and :form keys, and other thrown values are wrapped in such a map under :error.",
+    ), (
+        "save-image",
+        "([filename])",
+        "Saves the global environment to the file filename, to start from with --image. Lazy sequences are realized and saved as lists, failing past 100000 items.",
+    )]
+}
+
+/// Starts from an image saved by `save-image` instead of the prelude.
+fn load_image_file(filename: &str) -> Result<Rc<RefCell<Env>>, MalType> {
+    let bytes = fs::read(filename).or_else(|err| error(format!("{}: {}", filename, err)))?;
+    register_all_docs();
+    let (env, dynamic) =
+        load_image(&bytes, builtins(), apply_closure).map_err(|err| match err {
+            MalType::String(message) => MalType::String(format!("{}: {}", filename, message)),
+            err => err,
+        })?;
+    set_global_env(&env);
+    for name in dynamic {
+        declare_dynamic(&name);
+    }
+    for name in env.borrow().symbols() {
+        mirror_var(&name, &env.borrow().get(&name).unwrap());
+    }
+    Ok(env)
+}
+
+fn set_argv(env: &Rc<RefCell<Env>>, args: &[String]) {
     env.borrow_mut().set(
         "*ARGV*".to_string(),
-        if args.len() < 3 {
-            MalType::List(Vec::new(), None)
-        } else {
-            MalType::List(
-                args[2..]
-                    .iter()
-                    .map(|v| MalType::String(v.to_string()))
-                    .collect(),
-                None,
-            )
-        },
+        MalType::List(
+            args.iter()
+                .map(|v| MalType::String(v.to_string()))
+                .collect(),
+            None,
+        ),
     );
+}
+
+fn load_utils(env: Rc<RefCell<Env>>, prelude: bool) {
     env.borrow_mut().set(
         "*host-language*".to_string(),
         MalType::String("rust".to_string()),
     );
+    for (name, port) in [
+        ("*out*", Port::Stdout),
+        ("*err*", Port::Stderr),
+        ("*in*", Port::Stdin),
+    ] {
+        env.borrow_mut().set(name.to_string(), MalType::Port(port));
+        declare_dynamic(name);
+    }
 
-    let _ = rep("(def! not (fn* (a) (if a false true)))", &env);
-    let _ = rep(
-        r#"(def! load-file (fn* (f) (eval (read-string (str "(do " (slurp f) "\nnil)")))))"#,
-        &env,
-    );
-    let _ = rep(
-        r#"(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw "odd number of forms to cond")) (cons 'cond (rest (rest xs)))))))"#,
-        &env,
+    if !prelude {
+        return;
+    }
+    for (filename, source) in [("prelude.mal", PRELUDE), ("testing.mal", TESTING)] {
+        if let Err(err) = eval_source(filename, source, &env) {
+            eprintln!("Error: {}", print(&err));
+        }
+    }
+}
+
+fn root_env(env: &Rc<RefCell<Env>>) -> Rc<RefCell<Env>> {
+    let mut env = env.clone();
+    while let Some(outer) = env.clone().borrow().outer.clone() {
+        env = outer;
+    }
+    env
+}
+
+fn load_file(filename: &str, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
+    let content =
+        fs::read_to_string(filename).or_else(|err| error(format!("{}: {}", filename, err)))?;
+    eval_source(filename, &content, env)
+}
+
+/// Evaluates every form of `content` in the global environment, prefixing
+/// errors with their location in `filename`.
+fn eval_source(filename: &str, content: &str, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
+    let env = root_env(env);
+    for (index, (position, form)) in read_forms(content).enumerate() {
+        form.and_then(|ast| eval(&ast, &env))
+            .map_err(|err| located(err, filename, &position, index + 1))?;
+    }
+
+    Ok(MalType::Nil)
+}
+
+/// Adds the location of the form that raised `err` to it: as a prefix of a
+/// message, as keys of a thrown map that doesn't have one yet, or in a map
+/// wrapping any other thrown value under `:error`.
+fn located(err: MalType, filename: &str, position: &Position, form: usize) -> MalType {
+    let (mut map, metadata) = match err {
+        MalType::String(message) => {
+            return MalType::String(format!(
+                "{}:{}: form {}: {}",
+                filename, position, form, message
+            ))
+        }
+        MalType::Hashmap(ref map, _) if map.contains_key(&keyword("file")) => return err,
+        MalType::Hashmap(map, metadata) => (map, metadata),
+        err => (IndexMap::from([(keyword("error"), err)]), None),
+    };
+    for (key, value) in [
+        ("file", MalType::String(filename.to_string())),
+        ("line", MalType::Number(position.line as i64)),
+        ("column", MalType::Number(position.column as i64)),
+        ("form", MalType::Number(form as i64)),
+    ] {
+        map.insert(keyword(key), value);
+    }
+    MalType::Hashmap(map, metadata)
+}
+
+fn keyword(name: &str) -> Hashable {
+    Hashable::Keyword(name.to_string())
+}
+
+/// Runs the script in `filename`, or standard input when it is `-`, then
+/// calls its `-main` function, if it defines one, with `args`. Returns the
+/// exit code `-main` returns, or 0. A `-main` defined before the script, by
+/// `--load` or the image, is not called.
+fn run_file(env: &Rc<RefCell<Env>>, filename: &str, args: &[String]) -> Result<i32, String> {
+    let root = root_env(env);
+    let previous = root.borrow_mut().remove("-main");
+    let result = if filename == "-" {
+        io::read_to_string(io::stdin())
+            .or_else(|err| error(format!("<stdin>: {}", err)))
+            .and_then(|content| eval_source("<stdin>", &content, env))
+    } else {
+        load_file(filename, env)
+    };
+    let main = root.borrow().get("-main");
+    if let (None, Some(previous)) = (&main, previous) {
+        root.borrow_mut().set("-main".to_string(), previous);
+    }
+    result.map_err(|err| print(&err))?;
+
+    let result = match main {
+        Some(main @ (MalType::Function(_, _, _) | MalType::Closure(_, _))) => main
+            .apply(
+                &args
+                    .iter()
+                    .map(|arg| MalType::String(arg.clone()))
+                    .collect(),
+            )
+            .map_err(|err| print(&err))?,
+        _ => MalType::Nil,
+    };
+    match result {
+        MalType::Number(code @ 0..=255) => Ok(code as i32),
+        MalType::Number(code) => Err(format!(
+            "Expected -main to return an exit code from 0 to 255, but got {}",
+            code
+        )),
+        _ => Ok(0),
+    }
+}
+
+/// Runs the test files in `paths` with `run-tests`, each in a fresh
+/// environment, and prints the totals. Returns the exit code, 1 if a test
+/// failed.
+fn run_tests(options: &Options, paths: &[String]) -> i32 {
+    let paths = if paths.is_empty() {
+        vec![".".to_string()]
+    } else {
+        paths.to_vec()
+    };
+    let mut files = Vec::new();
+    for path in &paths {
+        if let Err(err) = test_files(Path::new(path), true, &mut files) {
+            eprintln!("Error: {}: {}", path, err);
+            return 1;
+        }
+    }
+    if files.is_empty() {
+        eprintln!("Error: No test files found");
+        return 1;
+    }
+
+    let keys = ["test", "pass", "fail", "error"];
+    let mut totals = [0; 4];
+    for file in &files {
+        let filename = file.to_string_lossy();
+        println!("Testing {}", filename);
+        let env = start(options);
+        let counts = load_file(&filename, &env).and_then(|_| {
+            eval(
+                &MalType::List(vec![MalType::symbol("run-tests")], None),
+                &env,
+            )
+        });
+        match counts {
+            Ok(MalType::Hashmap(counts, _)) => {
+                for (total, key) in totals.iter_mut().zip(keys) {
+                    if let Some(MalType::Number(count)) =
+                        counts.get(&Hashable::Keyword(key.to_string()))
+                    {
+                        *total += count;
+                    }
+                }
+            }
+            Ok(value) => {
+                eprintln!(
+                    "Error: Expected run-tests to return counts, but got {}",
+                    value
+                );
+                totals[3] += 1;
+            }
+            Err(err) => {
+                eprintln!("Error: {}", print(&err));
+                totals[3] += 1;
+            }
+        }
+        println!();
+    }
+
+    println!(
+        "Ran {} tests containing {} assertions in {} files.\n{} failures, {} errors.",
+        totals[0],
+        totals[1] + totals[2] + totals[3],
+        files.len(),
+        totals[2],
+        totals[3]
     );
+    if totals[2] + totals[3] > 0 {
+        1
+    } else {
+        0
+    }
 }
 
-fn run_file(env: &Rc<RefCell<Env>>, filename: &str) {
-    let load_file = format!("(load-file \"{}\")", filename);
-    if let Err(message) = rep(&load_file, &env) {
-        eprintln!("Error: {}", message);
+/// Adds `path` to `files` if it is a file named like a test, or given
+/// `explicit`ly, and searches it if it is a directory, skipping hidden ones
+/// and, unless given explicitly, symbolic links to directories.
+fn test_files(path: &Path, explicit: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let link = fs::symlink_metadata(path)?.file_type().is_symlink();
+    let metadata = match fs::metadata(path) {
+        Err(_) if link && !explicit => return Ok(()),
+        metadata => metadata?,
     };
+    if link && !explicit && metadata.is_dir() {
+        return Ok(());
+    }
+    if !metadata.is_dir() {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if explicit || name.ends_with("_test.mal") || name.ends_with("-test.mal") {
+            files.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+    for entry in entries {
+        let hidden = entry
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if !hidden {
+            test_files(&entry, false, files)?;
+        }
+    }
+    Ok(())
 }
 
 fn repl(env: &Rc<RefCell<Env>>) {
@@ -95,7 +616,7 @@ fn repl(env: &Rc<RefCell<Env>>) {
 fn rep(input: &str, env: &Rc<RefCell<Env>>) -> Result<String, String> {
     match read(input) {
         Ok(value) => eval(&value, env)
-            .and_then(|result| Ok(print(&result)))
+            .and_then(|result| print_result(&result, env))
             .map_err(|err| print(&err)),
         Err(value) => Err(print(&value)),
     }
@@ -119,8 +640,14 @@ fn eval(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
 
                 match &list[0] {
                     MalType::Symbol(name) if name == "def!" => {
-                        let key = list[1].as_symbol()?;
-                        let value = eval(&list[2], &env)?;
+                        let (key, dynamic) = definition_name(&list[1])?;
+                        let value = definition(list, key, &env)?;
+                        if dynamic {
+                            declare_dynamic(key);
+                        }
+                        if env.borrow().outer.is_none() {
+                            mirror_var(key, &value);
+                        }
                         env.borrow_mut().set(key.clone(), value.clone());
                         return Ok(value);
                     }
@@ -135,6 +662,22 @@ fn eval(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
                         env = new_env;
                         ast = list[2].clone();
                     }
+                    MalType::Symbol(name) if name == "binding" => return binding(list, &env),
+                    MalType::Symbol(name) if name == "set!" => {
+                        let key = list[1].as_symbol()?;
+                        if !is_bound(key) {
+                            return error(format!(
+                                "Can't change/establish root binding of: {} with set!",
+                                key
+                            ));
+                        }
+                        let value = eval(&list[2], &env)?;
+                        set_var(&env, key, value.clone())?;
+                        return Ok(value);
+                    }
+                    MalType::Symbol(name) if name == "do" && list.len() == 1 => {
+                        return Ok(MalType::Nil)
+                    }
                     MalType::Symbol(name) if name == "do" => {
                         match eval_ast(
                             &MalType::List(list[1..list.len() - 1].to_vec(), None),
@@ -165,16 +708,7 @@ fn eval(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
                                 params.clone(),
                                 body.clone(),
                                 env.clone(),
-                                |env, params, args, body| {
-                                    eval(
-                                        body,
-                                        &Rc::new(RefCell::new(Env::from(
-                                            Some(env.clone()),
-                                            &params,
-                                            &args,
-                                        ))),
-                                    )
-                                },
+                                apply_closure,
                             )),
                             None,
                         ));
@@ -192,13 +726,13 @@ fn eval(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
                     MalType::Symbol(name) if name == "quasiquote" => ast = quasiquote(&list[1]),
                     MalType::Symbol(name) if name == "defmacro!" => {
                         let key = list[1].as_symbol()?;
-                        let value = eval(&list[2], &env)?;
+                        let value = definition(list, key, &env)?;
                         return match &value {
-                            MalType::Closure(closure, _) => {
+                            MalType::Closure(closure, metadata) => {
                                 let mut closure = closure.clone();
                                 closure.is_macro = true;
                                 env.borrow_mut()
-                                    .set(key.clone(), MalType::Closure(closure, None));
+                                    .set(key.clone(), MalType::Closure(closure, metadata.clone()));
                                 Ok(value)
                             }
                             _ => error(format!("Expected function, but got {}", value)),
@@ -242,7 +776,9 @@ fn eval(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
                                     &list[1..].to_vec(),
                                 )));
                             }
-                            MalType::Function(function, _) => return function(&list[1..].to_vec()),
+                            MalType::Function(function, _, _) => {
+                                return function(&list[1..].to_vec())
+                            }
                             _ => return error(format!("Expected function but got {}", &list[0])),
                         }
                     }
@@ -275,7 +811,7 @@ fn eval_ast(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
             Ok(MalType::Vector(result, metadata.clone()))
         }
         MalType::Hashmap(map, metadata) => {
-            let mut result = HashMap::new();
+            let mut result = IndexMap::new();
             for (key, value) in map {
                 result.insert(key.clone(), eval(value, &env)?);
             }
@@ -289,6 +825,19 @@ fn print(ast: &MalType) -> String {
     pr_str(ast, true)
 }
 
+/// Prints a REPL result, pretty printed when `*repl-pprint*` is true or a
+/// line width.
+fn print_result(ast: &MalType, env: &Rc<RefCell<Env>>) -> Result<String, MalType> {
+    let setting = env.borrow().get("*repl-pprint*");
+    match setting {
+        Some(MalType::Number(width)) if width > 0 => pprint::pprint(ast, width as usize),
+        None | Some(MalType::Nil | MalType::False) => {
+            realize_printed(ast).map(|_| print_str(ast, true))
+        }
+        Some(_) => pprint::pprint(ast, pprint::DEFAULT_WIDTH),
+    }
+}
+
 fn quasiquote(ast: &MalType) -> MalType {
     match ast {
         MalType::List(list, _) => match &list.first() {
@@ -325,6 +874,189 @@ fn quasiquote_list(list: &Vec<MalType>) -> MalType {
     MalType::List(result, None)
 }
 
+fn apply_closure(
+    env: Rc<RefCell<Env>>,
+    params: &Vec<MalType>,
+    args: &Vec<MalType>,
+    body: &MalType,
+) -> Result<MalType, MalType> {
+    eval(
+        body,
+        &Rc::new(RefCell::new(Env::from(Some(env.clone()), params, args))),
+    )
+}
+
+/// Returns the symbol `def!` defines and whether it has `^:dynamic`
+/// metadata.
+fn definition_name(form: &MalType) -> Result<(&String, bool), MalType> {
+    match form {
+        MalType::List(list, _) if list.len() == 3 && list[0] == MalType::symbol("with-meta") => {
+            let dynamic = match &list[2] {
+                MalType::Hashmap(map, _) => !matches!(
+                    map.get(&Hashable::Keyword("dynamic".to_string())),
+                    None | Some(MalType::Nil) | Some(MalType::False)
+                ),
+                _ => false,
+            };
+            Ok((list[1].as_symbol()?, dynamic))
+        }
+        form => Ok((form.as_symbol()?, false)),
+    }
+}
+
+/// Evaluates `(binding [name value ...] body...)`, rebinding the dynamic vars
+/// while the body runs and restoring them afterwards, even on error.
+fn binding(list: &[MalType], env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
+    let bindings = list[1].as_list()?;
+    if bindings.len() % 2 != 0 {
+        return error("binding requires an even number of forms".to_string());
+    }
+
+    let mut names = Vec::new();
+    let mut values = Vec::new();
+    for pair in bindings.chunks(2) {
+        let name = pair[0].as_symbol()?;
+        if root_env(env).borrow().get(name).is_none() {
+            return error(format!("'{}' not found", name));
+        }
+        if !is_dynamic(name) {
+            return error(format!("Can't dynamically bind non-dynamic var: {}", name));
+        }
+        names.push(name.clone());
+        values.push(eval(&pair[1], env)?);
+    }
+
+    let mut saved = Vec::new();
+    for (name, value) in names.iter().zip(values) {
+        saved.push(set_var(env, name, value)?);
+    }
+    push_bindings(&names);
+
+    let mut result = Ok(MalType::Nil);
+    for form in &list[2..] {
+        result = eval(form, env);
+        if result.is_err() {
+            break;
+        }
+    }
+
+    pop_bindings(names.len());
+    for (name, value) in names.iter().zip(saved).rev() {
+        set_var(env, name, value)?;
+    }
+    result
+}
+
+/// Keeps the interpreter settings mirroring the global vars up to date with
+/// their new values.
+fn mirror_var(name: &str, value: &MalType) {
+    set_print_option(name, value);
+    set_port(name, value);
+    set_reader_option(name, value);
+}
+
+/// Sets the global var `name`, returning its previous value.
+fn set_var(env: &Rc<RefCell<Env>>, name: &str, value: MalType) -> Result<MalType, MalType> {
+    let env = root_env(env);
+    let previous = match env.borrow().get(name) {
+        Some(previous) => previous,
+        None => return error(format!("'{}' not found", name)),
+    };
+    mirror_var(name, &value);
+    env.borrow_mut().set(name.to_string(), value);
+    Ok(previous)
+}
+
+/// Evaluates the value of `(def! name doc? value)`, attaching the optional
+/// docstring to its metadata. Values that can't have metadata are defined
+/// without it.
+fn definition(list: &[MalType], name: &str, env: &Rc<RefCell<Env>>) -> Result<MalType, MalType> {
+    let (doc, expr) = match (list.len(), &list[2]) {
+        (4, MalType::String(doc)) => (Some(doc), &list[3]),
+        _ => (None, &list[2]),
+    };
+    let value = named(eval(expr, env)?, name);
+    let doc = match doc {
+        Some(doc) => doc,
+        None => return Ok(value),
+    };
+
+    let mut metadata = match value.meta() {
+        Ok(MalType::Hashmap(map, _)) => map,
+        Ok(_) => IndexMap::new(),
+        Err(_) => return Ok(value),
+    };
+    metadata.insert(
+        Hashable::Keyword("doc".to_string()),
+        MalType::String(doc.clone()),
+    );
+    value.with_meta(Some(Box::new(MalType::Hashmap(metadata, None))))
+}
+
+/// Returns the arglists and docstring of `value`, from the docs of the
+/// builtins or the metadata `def!` attaches.
+fn documentation(value: &MalType) -> (Option<MalType>, Option<String>) {
+    let doc = match value.meta() {
+        Ok(MalType::Hashmap(map, _)) => match map.get(&Hashable::Keyword("doc".to_string())) {
+            Some(MalType::String(doc)) => Some(doc.clone()),
+            _ => None,
+        },
+        _ => None,
+    };
+    match value {
+        MalType::Function(_, name, _) => match builtin_doc(name) {
+            Some((arglists, builtin)) => (Some(arglists), doc.or(Some(builtin))),
+            None => (None, doc),
+        },
+        MalType::Closure(closure, _) => (
+            Some(MalType::List(
+                vec![MalType::Vector(closure.params.clone(), None)],
+                None,
+            )),
+            doc,
+        ),
+        _ => (None, doc),
+    }
+}
+
+/// Describes `value` as `doc` prints it: its name, arglists, whether it is a
+/// macro and its docstring.
+fn doc_string(name: &str, value: &MalType) -> String {
+    let mut lines = vec!["-------------------------".to_string(), name.to_string()];
+    let (arglists, doc) = documentation(value);
+    if let Some(arglists) = arglists {
+        lines.push(print(&arglists));
+    }
+    if let MalType::Closure(closure, _) = value {
+        if closure.is_macro {
+            lines.push("Macro".to_string());
+        }
+    }
+    if let Some(doc) = doc {
+        lines.push(format!("  {}", doc));
+    }
+    lines.join("\n")
+}
+
+fn matches(pattern: &MalType, value: &str) -> Result<bool, MalType> {
+    match pattern {
+        MalType::String(pattern) => Ok(value.contains(pattern.as_str())),
+        MalType::Regex(regex) => Ok(regex.is_match(value)),
+        _ => error(format!("Expected string or regex, but got {}", pattern)),
+    }
+}
+
+/// Records `name` in a closure that doesn't have one yet.
+fn named(value: MalType, name: &str) -> MalType {
+    match value {
+        MalType::Closure(mut closure, metadata) if closure.name.is_none() => {
+            closure.name = Some(name.to_string());
+            MalType::Closure(closure, metadata)
+        }
+        value => value,
+    }
+}
+
 fn is_macro_call(ast: &MalType, env: &Rc<RefCell<Env>>) -> bool {
     if let MalType::List(list, _) = ast {
         if let Some(MalType::Symbol(name)) = list.first() {
@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+
+use regex::{Captures, Regex};
+
+use crate::printer::pr_str;
+use crate::types::{error, seq_iter, Function, MalType};
+
+pub fn ns() -> HashMap<&'static str, Function> {
+    let mut ns: HashMap<&'static str, Function> = HashMap::new();
+    ns.insert("subs", |args| {
+        let chars: Vec<char> = args[0].as_string()?.chars().collect();
+        let start = index_arg(args.get(1), 0)?;
+        let end = index_arg(args.get(2), chars.len())?;
+        if start > end || end > chars.len() {
+            return error(format!(
+                "Out of range: {}..{} of a string of length {}",
+                start,
+                end,
+                chars.len()
+            ));
+        }
+        Ok(MalType::String(chars[start..end].iter().collect()))
+    });
+    ns.insert("split", |args| {
+        let value = args[0].as_string()?;
+        if let MalType::Regex(regex) = &args[1] {
+            let parts = match args.get(2) {
+                Some(MalType::Number(limit)) if *limit > 0 => {
+                    regex.splitn(value, *limit as usize).collect::<Vec<&str>>()
+                }
+                _ => regex.split(value).collect(),
+            };
+            return Ok(MalType::Vector(
+                parts
+                    .into_iter()
+                    .map(|v| MalType::String(v.to_string()))
+                    .collect(),
+                None,
+            ));
+        }
+
+        let separator = pattern_arg(&args[1])?;
+        let parts: Vec<MalType> = match args.get(2) {
+            Some(MalType::Number(limit)) if *limit > 0 => value
+                .splitn(*limit as usize, separator.as_str())
+                .map(|v| MalType::String(v.to_string()))
+                .collect(),
+            _ if separator.is_empty() => value
+                .chars()
+                .map(|c| MalType::String(c.to_string()))
+                .collect(),
+            _ => value
+                .split(separator.as_str())
+                .map(|v| MalType::String(v.to_string()))
+                .collect(),
+        };
+        Ok(MalType::Vector(parts, None))
+    });
+    ns.insert("join", |args| {
+        let (separator, coll) = match args.len() {
+            1 => (String::new(), &args[0]),
+            _ => (pattern_arg(&args[0])?, &args[1]),
+        };
+        Ok(MalType::String(
+            seq_iter(coll)
+                .map(|v| v.map(|v| pr_str(&v, false)))
+                .collect::<Result<Vec<String>, MalType>>()?
+                .join(&separator),
+        ))
+    });
+    ns.insert("replace", |args| {
+        let value = args[0].as_string()?;
+        if let MalType::Regex(regex) = &args[1] {
+            return replace_regex(value, regex, &args[2]).map(MalType::String);
+        }
+
+        let from = pattern_arg(&args[1])?;
+        let to = pattern_arg(&args[2])?;
+        Ok(MalType::String(value.replace(from.as_str(), &to)))
+    });
+    ns.insert("trim", |args| {
+        Ok(MalType::String(args[0].as_string()?.trim().to_string()))
+    });
+    ns.insert("triml", |args| {
+        Ok(MalType::String(
+            args[0].as_string()?.trim_start().to_string(),
+        ))
+    });
+    ns.insert("trimr", |args| {
+        Ok(MalType::String(args[0].as_string()?.trim_end().to_string()))
+    });
+    ns.insert("upper-case", |args| {
+        Ok(MalType::String(args[0].as_string()?.to_uppercase()))
+    });
+    ns.insert("lower-case", |args| {
+        Ok(MalType::String(args[0].as_string()?.to_lowercase()))
+    });
+    ns.insert("starts-with?", |args| {
+        let value = args[0].as_string()?;
+        Ok(boolean(value.starts_with(pattern_arg(&args[1])?.as_str())))
+    });
+    ns.insert("ends-with?", |args| {
+        let value = args[0].as_string()?;
+        Ok(boolean(value.ends_with(pattern_arg(&args[1])?.as_str())))
+    });
+    ns.insert("includes?", |args| {
+        let value = args[0].as_string()?;
+        Ok(boolean(value.contains(pattern_arg(&args[1])?.as_str())))
+    });
+    ns.insert("index-of", |args| {
+        let value = args[0].as_string()?;
+        let pattern = pattern_arg(&args[1])?;
+        let from = index_arg(args.get(2), 0)?;
+        let offset = match value.char_indices().nth(from) {
+            Some((offset, _)) => offset,
+            None => return Ok(MalType::Nil),
+        };
+        Ok(value[offset..]
+            .find(pattern.as_str())
+            .map_or(MalType::Nil, |i| {
+                MalType::Number((from + value[offset..offset + i].chars().count()) as i64)
+            }))
+    });
+    ns.insert("blank?", |args| match &args[0] {
+        MalType::Nil => Ok(MalType::True),
+        value => Ok(boolean(value.as_string()?.trim().is_empty())),
+    });
+    ns.insert("split-lines", |args| {
+        Ok(MalType::Vector(
+            args[0]
+                .as_string()?
+                .lines()
+                .map(|v| MalType::String(v.to_string()))
+                .collect(),
+            None,
+        ))
+    });
+    ns.insert("re-pattern", |args| match &args[0] {
+        MalType::Regex(_) => Ok(args[0].clone()),
+        value => Regex::new(value.as_string()?)
+            .map(MalType::Regex)
+            .or_else(|err| error(err.to_string())),
+    });
+    ns.insert("re-find", |args| {
+        let regex = regex_arg(&args[0])?;
+        let value = args[1].as_string()?;
+        Ok(regex
+            .captures(value)
+            .map_or(MalType::Nil, |captures| match_value(&captures)))
+    });
+    ns.insert("re-matches", |args| {
+        let regex = regex_arg(&args[0])?;
+        let value = args[1].as_string()?;
+        let anchored = Regex::new(&format!(r"\A(?:{})\z", regex.as_str()))
+            .or_else(|err| error(err.to_string()))?;
+        Ok(anchored
+            .captures(value)
+            .map_or(MalType::Nil, |captures| match_value(&captures)))
+    });
+    ns.insert("re-seq", |args| {
+        let regex = regex_arg(&args[0])?;
+        let value = args[1].as_string()?;
+        let matches: Vec<MalType> = regex
+            .captures_iter(value)
+            .map(|captures| match_value(&captures))
+            .collect();
+        Ok(if matches.is_empty() {
+            MalType::Nil
+        } else {
+            MalType::List(matches, None)
+        })
+    });
+    ns.insert("format", |args| {
+        format(args[0].as_string()?, &args[1..]).map(MalType::String)
+    });
+    ns
+}
+
+/// Arglists and docstrings of the builtins in `ns()`.
+pub fn docs() -> Vec<(&'static str, &'static str, &'static str)> {
+    vec![
+        ("subs", "([s start] [s start end])", "Returns the characters of s from start to end, exclusive."),
+        ("split", "([s separator] [s separator limit])", "Splits s around a string, character or regex separator."),
+        ("join", "([coll] [separator coll])", "Returns the items of coll printed as by str and joined by separator."),
+        ("replace", "([s match replacement])", "Replaces every occurrence of match in s. With a regex, replacement may refer to groups or be a function."),
+        ("trim", "([s])", "Removes whitespace from both ends of s."),
+        ("triml", "([s])", "Removes whitespace from the start of s."),
+        ("trimr", "([s])", "Removes whitespace from the end of s."),
+        ("upper-case", "([s])", "Converts s to upper case."),
+        ("lower-case", "([s])", "Converts s to lower case."),
+        ("starts-with?", "([s prefix])", "Returns true if s starts with prefix."),
+        ("ends-with?", "([s suffix])", "Returns true if s ends with suffix."),
+        ("includes?", "([s substring])", "Returns true if s contains substring."),
+        ("index-of", "([s value] [s value from])", "Returns the character index of value in s, or nil."),
+        ("blank?", "([s])", "Returns true if s is nil, empty or only whitespace."),
+        ("split-lines", "([s])", "Splits s into a vector of lines."),
+        ("re-pattern", "([s])", "Compiles s into a regex."),
+        ("re-find", "([regex s])", "Returns the first match of regex in s, or nil."),
+        ("re-matches", "([regex s])", "Returns the match of regex against the whole of s, or nil."),
+        ("re-seq", "([regex s])", "Returns a list of every match of regex in s, or nil."),
+        ("format", "([template & args])", "Formats args following the printf-style directives in template."),
+    ]
+}
+
+fn boolean(value: bool) -> MalType {
+    if value {
+        MalType::True
+    } else {
+        MalType::False
+    }
+}
+
+fn pattern_arg(value: &MalType) -> Result<String, MalType> {
+    match value {
+        MalType::String(value) => Ok(value.clone()),
+        MalType::Char(c) => Ok(c.to_string()),
+        _ => error(format!("Expected string or char, but got {}", value)),
+    }
+}
+
+fn regex_arg(value: &MalType) -> Result<&Regex, MalType> {
+    match value {
+        MalType::Regex(regex) => Ok(regex),
+        _ => error(format!("Expected regex, but got {}", value)),
+    }
+}
+
+/// Returns the whole match when `captures` has no groups, otherwise a vector
+/// of the whole match followed by each group (nil when a group didn't match).
+fn match_value(captures: &Captures) -> MalType {
+    if captures.len() == 1 {
+        return MalType::String(captures[0].to_string());
+    }
+
+    MalType::Vector(
+        captures
+            .iter()
+            .map(|group| group.map_or(MalType::Nil, |v| MalType::String(v.as_str().to_string())))
+            .collect(),
+        None,
+    )
+}
+
+/// Replaces every match of `regex` in `value`. `replacement` is either a
+/// string, where `$1` or `${name}` refer to groups, or a function called
+/// with each match as returned by `re-find`.
+fn replace_regex(value: &str, regex: &Regex, replacement: &MalType) -> Result<String, MalType> {
+    let mut result = String::new();
+    let mut last = 0;
+    for captures in regex.captures_iter(value) {
+        let whole = captures.get(0).unwrap();
+        result.push_str(&value[last..whole.start()]);
+        match replacement {
+            MalType::String(template) => captures.expand(template, &mut result),
+            MalType::Closure(closure, _) => result.push_str(&pr_str(
+                &closure.apply(&vec![match_value(&captures)])?,
+                false,
+            )),
+            MalType::Function(f, _, _) => {
+                result.push_str(&pr_str(&f(&vec![match_value(&captures)])?, false))
+            }
+            _ => {
+                return error(format!(
+                    "Expected string or function, but got {}",
+                    replacement
+                ))
+            }
+        }
+        last = whole.end();
+    }
+    result.push_str(&value[last..]);
+    Ok(result)
+}
+
+fn index_arg(value: Option<&MalType>, default: usize) -> Result<usize, MalType> {
+    match value {
+        None | Some(MalType::Nil) => Ok(default),
+        Some(MalType::Number(index)) if *index >= 0 => Ok(*index as usize),
+        Some(value) => error(format!("Expected non-negative number, but got {}", value)),
+    }
+}
+
+/// Formats `args` following the printf-style directives in `template`:
+/// `%[flags][width][.precision]conversion`, where flags are `-` (left align)
+/// and `0` (zero pad) and conversions are `s`, `d`, `x`, `X`, `o`, `b`, `c`,
+/// `f` and `%`.
+fn format(template: &str, args: &[MalType]) -> Result<String, MalType> {
+    let mut result = String::new();
+    let mut chars = template.chars().peekable();
+    let mut args = args.iter();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+
+        let mut left_align = false;
+        let mut zero_pad = false;
+        while let Some(flag) = chars.next_if(|c| *c == '-' || *c == '0') {
+            match flag {
+                '-' => left_align = true,
+                _ => zero_pad = true,
+            }
+        }
+        let mut width = String::new();
+        while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+            width.push(digit);
+        }
+        let mut precision = None;
+        if chars.next_if_eq(&'.').is_some() {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+                digits.push(digit);
+            }
+            precision = Some(digits.parse::<usize>().unwrap_or(0));
+        }
+
+        let conversion = match chars.next() {
+            Some('%') => {
+                result.push('%');
+                continue;
+            }
+            Some(conversion) => conversion,
+            None => return error("Incomplete format directive at end of string".to_string()),
+        };
+        let arg = match args.next() {
+            Some(arg) => arg,
+            None => return error(format!("Missing argument for %{}", conversion)),
+        };
+        let value = match (conversion, arg) {
+            ('s', arg) => {
+                let value = pr_str(arg, false);
+                match precision {
+                    Some(precision) => value.chars().take(precision).collect(),
+                    None => value,
+                }
+            }
+            ('d', MalType::Number(n)) => n.to_string(),
+            ('x', MalType::Number(n)) => format!("{:x}", n),
+            ('X', MalType::Number(n)) => format!("{:X}", n),
+            ('o', MalType::Number(n)) => format!("{:o}", n),
+            ('b', MalType::Number(n)) => format!("{:b}", n),
+            ('f', MalType::Number(n)) => format!("{:.*}", precision.unwrap_or(6), *n as f64),
+            ('c', MalType::Char(c)) => c.to_string(),
+            ('c', MalType::Number(n)) => u32::try_from(*n)
+                .ok()
+                .and_then(char::from_u32)
+                .map(|c| c.to_string())
+                .ok_or_else(|| MalType::String(format!("Invalid code point {}", n)))?,
+            ('d' | 'x' | 'X' | 'o' | 'b' | 'f' | 'c', arg) => {
+                return error(format!("Unexpected argument {} for %{}", arg, conversion))
+            }
+            _ => return error(format!("Unknown format conversion %{}", conversion)),
+        };
+
+        let width = width.parse::<usize>().unwrap_or(0);
+        let padding = width.saturating_sub(value.chars().count());
+        if left_align {
+            result.push_str(&value);
+            result.push_str(&" ".repeat(padding));
+        } else if zero_pad && conversion != 's' && conversion != 'c' {
+            let (sign, digits) = match value.strip_prefix('-') {
+                Some(digits) => ("-", digits),
+                None => ("", value.as_str()),
+            };
+            result.push_str(sign);
+            result.push_str(&"0".repeat(padding));
+            result.push_str(digits);
+        } else {
+            result.push_str(&" ".repeat(padding));
+            result.push_str(&value);
+        }
+    }
+    Ok(result)
+}
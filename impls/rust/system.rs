@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsStr,
+    io::{self, Write},
+    process::{Command, Stdio},
+    thread,
+};
+
+use indexmap::IndexMap;
+
+use crate::printer::pr_str;
+use crate::types::{error, Function, Hashable, MalType};
+
+pub fn ns() -> HashMap<&'static str, Function> {
+    let mut ns: HashMap<&'static str, Function> = HashMap::new();
+    ns.insert("getenv", |args| match args.first() {
+        Some(name) => Ok(env::var_os(name.as_string()?)
+            .map_or(MalType::Nil, |value| MalType::String(lossy(&value)))),
+        None => Ok(MalType::Hashmap(
+            env::vars_os()
+                .map(|(name, value)| {
+                    (
+                        Hashable::String(lossy(&name)),
+                        MalType::String(lossy(&value)),
+                    )
+                })
+                .collect(),
+            None,
+        )),
+    });
+    ns.insert("setenv", |args| {
+        let name = args[0].as_string()?;
+        if name.is_empty() || name.contains(['=', '\0']) {
+            return error(format!("Invalid environment variable name {:?}", name));
+        }
+        match &args[1] {
+            MalType::Nil => env::remove_var(name),
+            value => {
+                let value = value.as_string()?;
+                if value.contains('\0') {
+                    return error(format!("Invalid value for environment variable {}", name));
+                }
+                env::set_var(name, value)
+            }
+        }
+        Ok(MalType::Nil)
+    });
+    ns.insert("exit", |args| {
+        let code = match args.first() {
+            None | Some(MalType::Nil) => 0,
+            Some(MalType::Number(code @ 0..=255)) => *code as i32,
+            Some(MalType::Number(code)) => {
+                return error(format!(
+                    "Expected an exit code from 0 to 255, but got {}",
+                    code
+                ))
+            }
+            Some(value) => return error(format!("Expected number, but got {}", value)),
+        };
+        let _ = io::stdout().flush();
+        std::process::exit(code)
+    });
+    ns.insert("sh", |args| {
+        let split = args
+            .iter()
+            .position(|arg| matches!(arg, MalType::Keyword(_)))
+            .unwrap_or(args.len());
+        let mut options = IndexMap::new();
+        for pair in args[split..].chunks(2) {
+            match pair {
+                [key, value] => options.insert(Hashable::new(key)?, value.clone()),
+                _ => return error(format!("Missing value for option {}", pair[0])),
+            };
+        }
+        run(&args[..split], &options)
+    });
+    ns.insert("process", |args| {
+        let command = match &args[0] {
+            MalType::List(command, _) | MalType::Vector(command, _) => command,
+            value => return error(format!("Expected list/vector, but got {}", value)),
+        };
+        match args.get(1) {
+            None | Some(MalType::Nil) => run(command, &IndexMap::new()),
+            Some(MalType::Hashmap(options, _)) => run(command, options),
+            Some(value) => error(format!("Expected hashmap, but got {}", value)),
+        }
+    });
+    ns
+}
+
+/// Arglists and docstrings of the builtins in `ns()`.
+pub fn docs() -> Vec<(&'static str, &'static str, &'static str)> {
+    vec![
+        ("getenv", "([] [name])", "Returns the value of an environment variable, or nil. Without a name, returns a map of every variable. Invalid UTF-8 is replaced with U+FFFD."),
+        ("setenv", "([name value])", "Sets an environment variable, or removes it when value is nil. The name must not be empty or contain = or NUL, and the value must not contain NUL."),
+        ("exit", "([] [code])", "Exits the interpreter with code, from 0 to 255 and 0 by default."),
+        ("sh", "([command & args-and-options])", "Runs command with args and waits for it, returning a map of its :exit code, :out and :err. Takes the :in, :dir and :env options of process."),
+        ("process", "([command] [command options])", "Runs the command and args in a list and waits for it, returning a map of its :exit code, :out and :err. Options are :in, a string written to its input, :dir, its working directory, and :env, a map of variables added to its environment."),
+    ]
+}
+
+/// Converts an environment variable name or value to a string, replacing
+/// invalid UTF-8.
+fn lossy(value: &OsStr) -> String {
+    value.to_string_lossy().into_owned()
+}
+
+/// Runs `command` with its arguments, waiting for it to exit.
+fn run(command: &[MalType], options: &IndexMap<Hashable, MalType>) -> Result<MalType, MalType> {
+    let (program, args) = match command.split_first() {
+        Some((program, args)) => (program.as_string()?, args),
+        None => return error("Expected a command to run".to_string()),
+    };
+    let mut process = Command::new(program);
+    for arg in args {
+        process.arg(arg.as_string()?);
+    }
+
+    let mut input = None;
+    for (key, value) in options {
+        match (key, value) {
+            (_, MalType::Nil) => {}
+            (Hashable::Keyword(key), MalType::String(value)) if key == "in" => {
+                input = Some(value.clone());
+            }
+            (Hashable::Keyword(key), MalType::String(dir)) if key == "dir" => {
+                process.current_dir(dir);
+            }
+            (Hashable::Keyword(key), MalType::Hashmap(vars, _)) if key == "env" => {
+                for (name, value) in vars {
+                    let name = match name {
+                        Hashable::Keyword(name) | Hashable::String(name) => name.clone(),
+                        name => pr_str(&name.to_mal(), false),
+                    };
+                    process.env(name, pr_str(value, false));
+                }
+            }
+            (key, value) => {
+                return error(format!(
+                    "Invalid option {} {}",
+                    pr_str(&key.to_mal(), true),
+                    pr_str(value, true)
+                ))
+            }
+        }
+    }
+
+    let mut child = process
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .or_else(|err| error(format!("{}: {}", program, err)))?;
+    // Writing from another thread lets the child fill its output pipes
+    // without blocking on its input.
+    let writer = match (input, child.stdin.take()) {
+        (Some(input), Some(mut stdin)) => {
+            Some(thread::spawn(move || stdin.write_all(input.as_bytes())))
+        }
+        _ => None,
+    };
+    let output = child
+        .wait_with_output()
+        .or_else(|err| error(format!("{}: {}", program, err)))?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    Ok(MalType::Hashmap(
+        IndexMap::from([
+            (
+                Hashable::Keyword("exit".to_string()),
+                output
+                    .status
+                    .code()
+                    .map_or(MalType::Nil, |code| MalType::Number(code as i64)),
+            ),
+            (
+                Hashable::Keyword("out".to_string()),
+                MalType::String(String::from_utf8_lossy(&output.stdout).to_string()),
+            ),
+            (
+                Hashable::Keyword("err".to_string()),
+                MalType::String(String::from_utf8_lossy(&output.stderr).to_string()),
+            ),
+        ]),
+        None,
+    ))
+}
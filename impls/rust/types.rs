@@ -1,10 +1,19 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+use std::{
+    cell::RefCell,
+    fmt::Debug,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use indexmap::{IndexMap, IndexSet};
+use regex::Regex;
 
 use crate::{env::Env, printer::pr_str};
 
 pub type Function = fn(&Vec<MalType>) -> Result<MalType, MalType>;
 pub type ClosureFunction =
     fn(Rc<RefCell<Env>>, &Vec<MalType>, &Vec<MalType>, &MalType) -> Result<MalType, MalType>;
+pub type Thunk = Rc<dyn Fn() -> Result<MalType, MalType>>;
 
 #[derive(Clone)]
 pub struct Closure {
@@ -13,6 +22,8 @@ pub struct Closure {
     pub env: Rc<RefCell<Env>>,
     pub f: ClosureFunction,
     pub is_macro: bool,
+    /// Name of the first `def!` or `defmacro!` the closure was bound to.
+    pub name: Option<String>,
 }
 
 impl Closure {
@@ -28,6 +39,7 @@ impl Closure {
             env,
             f,
             is_macro: false,
+            name: None,
         }
     }
 
@@ -39,6 +51,7 @@ impl Closure {
 impl Debug for Closure {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Closure")
+            .field("name", &self.name)
             .field("params", &self.params)
             .field("body", &self.body)
             .field("env", &self.env)
@@ -46,10 +59,362 @@ impl Debug for Closure {
     }
 }
 
+enum LazyState {
+    Pending(Thunk),
+    Realized(Option<(MalType, MalType)>),
+}
+
+/// A sequence whose head is computed on demand by a thunk returning any
+/// seqable value. The result is cached, so clones share the realized cells.
+#[derive(Clone)]
+pub struct LazySeq(Rc<RefCell<LazyState>>);
+
+impl LazySeq {
+    pub fn new(thunk: impl Fn() -> Result<MalType, MalType> + 'static) -> Self {
+        LazySeq(Rc::new(RefCell::new(LazyState::Pending(Rc::new(thunk)))))
+    }
+
+    pub fn cons(first: MalType, rest: MalType) -> Self {
+        LazySeq(Rc::new(RefCell::new(LazyState::Realized(Some((
+            first, rest,
+        ))))))
+    }
+
+    /// Realizes the head of the sequence, returning its first element and the
+    /// rest, or `None` if it is empty. A failed thunk is retried next time.
+    pub fn step(&self) -> Result<Option<(MalType, MalType)>, MalType> {
+        let thunk = match &*self.0.borrow() {
+            LazyState::Realized(cell) => return Ok(cell.clone()),
+            LazyState::Pending(thunk) => thunk.clone(),
+        };
+
+        let cell = seq_step(&thunk()?)?;
+        *self.0.borrow_mut() = LazyState::Realized(cell.clone());
+        Ok(cell)
+    }
+}
+
+// Dropping a long realized sequence would otherwise recurse once per cell.
+impl Drop for LazySeq {
+    fn drop(&mut self) {
+        let mut next = self.take_rest();
+        while let Some(MalType::LazySeq(seq)) = next {
+            next = seq.take_rest();
+        }
+    }
+}
+
+impl LazySeq {
+    fn take_rest(&self) -> Option<MalType> {
+        if Rc::strong_count(&self.0) != 1 {
+            return None;
+        }
+
+        match &mut *self.0.borrow_mut() {
+            LazyState::Realized(Some((_, rest))) => Some(std::mem::replace(rest, MalType::Nil)),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the first element and the rest of any seqable value, or `None`
+/// if it is empty.
+pub fn seq_step(value: &MalType) -> Result<Option<(MalType, MalType)>, MalType> {
+    match value {
+        MalType::Nil => Ok(None),
+        MalType::List(list, _) | MalType::Vector(list, _) => Ok(list
+            .first()
+            .map(|first| (first.clone(), slice_seq(list.as_slice().into(), 1)))),
+        MalType::String(value) => Ok(value.chars().next().map(|c| {
+            (
+                MalType::Char(c),
+                slice_seq(value.chars().map(MalType::Char).collect(), 1),
+            )
+        })),
+        MalType::Hashmap(map, _) => {
+            let mut entries = map_entries(map);
+            if entries.is_empty() {
+                return Ok(None);
+            }
+            let first = entries.remove(0);
+            Ok(Some((first, MalType::List(entries, None))))
+        }
+        MalType::Set(set, _) => Ok(set.first().map(|first| {
+            (
+                first.to_mal(),
+                slice_seq(set.iter().map(Hashable::to_mal).collect(), 1),
+            )
+        })),
+        MalType::LazySeq(seq) => seq.step(),
+        _ => error(format!("Expected seqable value, but got {}", value)),
+    }
+}
+
+/// The items of `items` from `index` on. Stepping through it shares `items`
+/// instead of copying the rest of them.
+fn slice_seq(items: Rc<[MalType]>, index: usize) -> MalType {
+    MalType::LazySeq(LazySeq::new(move || {
+        Ok(match items.get(index) {
+            Some(item) => MalType::LazySeq(LazySeq::cons(
+                item.clone(),
+                slice_seq(items.clone(), index + 1),
+            )),
+            None => MalType::Nil,
+        })
+    }))
+}
+
+/// Iterates over the items of a seqable value, realizing a lazy sequence only
+/// as far as it is consumed.
+pub fn seq_iter(value: &MalType) -> SeqIter<'_> {
+    match value {
+        MalType::List(list, _) | MalType::Vector(list, _) => SeqIter::Slice(list.iter()),
+        value => SeqIter::Seq(Some(value.clone())),
+    }
+}
+
+pub enum SeqIter<'a> {
+    Slice(std::slice::Iter<'a, MalType>),
+    Seq(Option<MalType>),
+}
+
+impl Iterator for SeqIter<'_> {
+    type Item = Result<MalType, MalType>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SeqIter::Slice(items) => items.next().cloned().map(Ok),
+            SeqIter::Seq(current) => match seq_step(&current.take()?) {
+                Ok(Some((first, rest))) => {
+                    *current = Some(rest);
+                    Some(Ok(first))
+                }
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            },
+        }
+    }
+}
+
+/// Realizes every element of a seqable value.
+/// Compares `a` and `b` like `==`, but steps through lazy sequences one
+/// element at a time, stopping at the first difference, and returns the
+/// errors they throw.
+pub fn equals(a: &MalType, b: &MalType) -> Result<bool, MalType> {
+    let sequential = |value: &MalType| {
+        matches!(
+            value,
+            MalType::List(_, _) | MalType::Vector(_, _) | MalType::LazySeq(_)
+        )
+    };
+    if !sequential(a) || !sequential(b) {
+        return Ok(a == b);
+    }
+    let mut b_items = seq_iter(b);
+    for a_item in seq_iter(a) {
+        let a_item = a_item?;
+        match b_items.next().transpose()? {
+            Some(b_item) if equals(&a_item, &b_item)? => {}
+            _ => return Ok(false),
+        }
+    }
+    Ok(b_items.next().transpose()?.is_none())
+}
+
+pub fn seq_to_vec(value: &MalType) -> Result<Vec<MalType>, MalType> {
+    let mut result = Vec::new();
+    let mut current = value.clone();
+    loop {
+        match current {
+            MalType::Nil => break,
+            MalType::List(list, _) | MalType::Vector(list, _) => {
+                result.extend(list);
+                break;
+            }
+            MalType::String(value) => {
+                result.extend(value.chars().map(MalType::Char));
+                break;
+            }
+            MalType::Hashmap(map, _) => {
+                result.extend(map_entries(&map));
+                break;
+            }
+            MalType::Set(set, _) => {
+                result.extend(set.iter().map(Hashable::to_mal));
+                break;
+            }
+            MalType::LazySeq(seq) => match seq.step()? {
+                Some((first, rest)) => {
+                    result.push(first);
+                    current = rest;
+                }
+                None => break,
+            },
+            value => return error(format!("Expected seqable value, but got {}", value)),
+        }
+    }
+    Ok(result)
+}
+
+/// Realizes every lazy sequence nested in `value`, surfacing any error their
+/// thunks raise.
+pub fn realize(value: &MalType) -> Result<(), MalType> {
+    match value {
+        MalType::List(list, _) | MalType::Vector(list, _) => list.iter().try_for_each(realize),
+        MalType::Hashmap(map, _) => map.values().try_for_each(realize),
+        MalType::LazySeq(_) => seq_to_vec(value)?.iter().try_for_each(realize),
+        _ => Ok(()),
+    }
+}
+
+/// One stage of a transducer pipeline.
+#[derive(Clone, Debug)]
+pub enum Xform {
+    Map(MalType),
+    Filter(MalType, bool),
+    Take(i64),
+    Drop(i64),
+    TakeWhile(MalType),
+    /// A user-defined stage: a step function returning the items to pass on
+    /// for each item, wrapped in `reduced` to stop after them, and an
+    /// optional function returning the items to flush once the input ends.
+    Custom(MalType, Option<MalType>),
+}
+
+/// A stream printing functions write to and `read-line` reads from.
+#[derive(Clone, Debug)]
+pub enum Port {
+    Stdout,
+    Stderr,
+    Stdin,
+    /// Collects what is written to it, as `with-out-str` does.
+    Writer(Rc<RefCell<String>>),
+    /// Reads from what remains of a string, as `with-in-str` does.
+    Reader(Rc<RefCell<String>>),
+}
+
+impl Port {
+    pub fn write(&self, value: &str) -> Result<(), MalType> {
+        let result = match self {
+            Port::Stdout => io::stdout().write_all(value.as_bytes()),
+            Port::Stderr => io::stderr().write_all(value.as_bytes()),
+            Port::Writer(buffer) => {
+                buffer.borrow_mut().push_str(value);
+                Ok(())
+            }
+            Port::Stdin | Port::Reader(_) => {
+                return error("Cannot write to an input port".to_string())
+            }
+        };
+        result.or_else(|err| error(err.to_string()))
+    }
+
+    pub fn flush(&self) -> Result<(), MalType> {
+        let result = match self {
+            Port::Stdout => io::stdout().flush(),
+            Port::Stderr => io::stderr().flush(),
+            _ => Ok(()),
+        };
+        result.or_else(|err| error(err.to_string()))
+    }
+
+    /// Reads a line without its line ending, or `None` at the end of input.
+    pub fn read_line(&self) -> Result<Option<String>, MalType> {
+        let mut line = match self {
+            Port::Stdin => {
+                let mut line = String::new();
+                match io::stdin().lock().read_line(&mut line) {
+                    Ok(0) => return Ok(None),
+                    Ok(_) => line,
+                    Err(err) => return error(err.to_string()),
+                }
+            }
+            Port::Reader(input) => {
+                let mut input = input.borrow_mut();
+                if input.is_empty() {
+                    return Ok(None);
+                }
+                let end = input.find('\n').map_or(input.len(), |i| i + 1);
+                input.drain(..end).collect()
+            }
+            Port::Stdout | Port::Stderr | Port::Writer(_) => {
+                return error("Cannot read from an output port".to_string())
+            }
+        };
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+}
+
+impl PartialEq for Port {
+    fn eq(&self, other: &Port) -> bool {
+        match (self, other) {
+            (Port::Stdout, Port::Stdout) => true,
+            (Port::Stderr, Port::Stderr) => true,
+            (Port::Stdin, Port::Stdin) => true,
+            (Port::Writer(a), Port::Writer(b)) | (Port::Reader(a), Port::Reader(b)) => {
+                Rc::ptr_eq(a, b)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A value usable as a hash-map key.
 #[derive(Clone, Hash, PartialEq, Eq, Debug)]
 pub enum Hashable {
     Keyword(String),
     String(String),
+    Symbol(String),
+    Number(i64),
+    Char(char),
+    True,
+    False,
+    Nil,
+}
+
+impl Hashable {
+    pub fn new(value: &MalType) -> Result<Self, MalType> {
+        match value {
+            MalType::String(value) => Ok(Hashable::String(value.clone())),
+            MalType::Keyword(value) => Ok(Hashable::Keyword(value.clone())),
+            MalType::Symbol(value) => Ok(Hashable::Symbol(value.clone())),
+            MalType::Number(value) => Ok(Hashable::Number(*value)),
+            MalType::Char(value) => Ok(Hashable::Char(*value)),
+            MalType::True => Ok(Hashable::True),
+            MalType::False => Ok(Hashable::False),
+            MalType::Nil => Ok(Hashable::Nil),
+            _ => error(format!(
+                "Expected string, keyword, symbol, number, character, boolean or nil as a key, but got {}",
+                value
+            )),
+        }
+    }
+
+    pub fn to_mal(&self) -> MalType {
+        match self {
+            Hashable::String(value) => MalType::String(value.clone()),
+            Hashable::Keyword(value) => MalType::Keyword(value.clone()),
+            Hashable::Symbol(value) => MalType::Symbol(value.clone()),
+            Hashable::Number(value) => MalType::Number(*value),
+            Hashable::Char(value) => MalType::Char(*value),
+            Hashable::True => MalType::True,
+            Hashable::False => MalType::False,
+            Hashable::Nil => MalType::Nil,
+        }
+    }
+}
+
+/// Returns the entries of `map` as `[key value]` vectors, in insertion order.
+pub fn map_entries(map: &IndexMap<Hashable, MalType>) -> Vec<MalType> {
+    map.iter()
+        .map(|(key, value)| MalType::Vector(vec![key.to_mal(), value.clone()], None))
+        .collect()
 }
 
 #[derive(Clone)]
@@ -58,15 +423,22 @@ pub enum MalType {
     False,
     Nil,
     Number(i64),
+    Char(char),
     Symbol(String),
     Keyword(String),
     String(String),
+    Regex(Regex),
     List(Vec<MalType>, Option<Box<MalType>>),
-    Hashmap(HashMap<Hashable, MalType>, Option<Box<MalType>>),
+    Hashmap(IndexMap<Hashable, MalType>, Option<Box<MalType>>),
     Vector(Vec<MalType>, Option<Box<MalType>>),
-    Function(Function, Option<Box<MalType>>),
+    Set(IndexSet<Hashable>, Option<Box<MalType>>),
+    Function(Function, &'static str, Option<Box<MalType>>),
     Closure(Box<Closure>, Option<Box<MalType>>),
     Atom(Rc<RefCell<MalType>>),
+    LazySeq(LazySeq),
+    Transducer(Rc<Vec<Xform>>),
+    Reduced(Box<MalType>),
+    Port(Port),
 }
 
 pub fn error<T>(message: String) -> Result<T, MalType> {
@@ -98,7 +470,7 @@ impl MalType {
 
     pub fn as_function(&self) -> Result<&Function, MalType> {
         match self {
-            MalType::Function(f, _) => Ok(f),
+            MalType::Function(f, _, _) => Ok(f),
             value => {
                 return error(format!(
                     "Expected function but got {}.",
@@ -108,9 +480,49 @@ impl MalType {
         }
     }
 
+    pub fn apply(&self, args: &Vec<MalType>) -> Result<MalType, MalType> {
+        match self {
+            MalType::Function(f, _, _) => f(args),
+            MalType::Closure(closure, _) => closure.apply(args),
+            value => error(format!("Expected function, but got {}", value)),
+        }
+    }
+
     pub fn symbol(name: &str) -> Self {
         MalType::Symbol(name.to_string())
     }
+
+    pub fn meta(&self) -> Result<MalType, MalType> {
+        match self {
+            MalType::List(_, metadata)
+            | MalType::Vector(_, metadata)
+            | MalType::Hashmap(_, metadata)
+            | MalType::Set(_, metadata)
+            | MalType::Function(_, _, metadata)
+            | MalType::Closure(_, metadata) => Ok(metadata
+                .as_ref()
+                .map_or(MalType::Nil, |v| v.as_ref().clone())),
+            _ => error(format!(
+                "Expected list/vector/hashmap/set/function, but got {}",
+                self
+            )),
+        }
+    }
+
+    pub fn with_meta(&self, metadata: Option<Box<MalType>>) -> Result<MalType, MalType> {
+        match self {
+            MalType::List(list, _) => Ok(MalType::List(list.clone(), metadata)),
+            MalType::Vector(list, _) => Ok(MalType::Vector(list.clone(), metadata)),
+            MalType::Hashmap(map, _) => Ok(MalType::Hashmap(map.clone(), metadata)),
+            MalType::Set(set, _) => Ok(MalType::Set(set.clone(), metadata)),
+            MalType::Function(f, name, _) => Ok(MalType::Function(*f, name, metadata)),
+            MalType::Closure(closure, _) => Ok(MalType::Closure(closure.clone(), metadata)),
+            _ => error(format!(
+                "Expected list/vector/hashmap/set/function, but got {}",
+                self
+            )),
+        }
+    }
 }
 
 impl PartialEq for MalType {
@@ -120,14 +532,25 @@ impl PartialEq for MalType {
             (MalType::False, MalType::False) => true,
             (MalType::Nil, MalType::Nil) => true,
             (MalType::Number(a), MalType::Number(b)) => a == b,
+            (MalType::Char(a), MalType::Char(b)) => a == b,
             (MalType::Symbol(a), MalType::Symbol(b)) => a == b,
             (MalType::Keyword(a), MalType::Keyword(b)) => a == b,
             (MalType::String(a), MalType::String(b)) => a == b,
+            (MalType::Regex(a), MalType::Regex(b)) => a.as_str() == b.as_str(),
+            (MalType::Port(a), MalType::Port(b)) => a == b,
             (MalType::List(a, _), MalType::List(b, _)) => a == b,
             (MalType::Hashmap(a, _), MalType::Hashmap(b, _)) => a == b,
             (MalType::Vector(a, _), MalType::Vector(b, _)) => a == b,
+            (MalType::Set(a, _), MalType::Set(b, _)) => a == b,
             (MalType::List(a, _), MalType::Vector(b, _)) => a == b,
             (MalType::Vector(a, _), MalType::List(b, _)) => a == b,
+            (
+                MalType::LazySeq(_),
+                MalType::LazySeq(_) | MalType::List(_, _) | MalType::Vector(_, _),
+            )
+            | (MalType::List(_, _) | MalType::Vector(_, _), MalType::LazySeq(_)) => {
+                equals(self, other).unwrap_or(false)
+            }
             _ => false,
         }
     }
@@ -142,15 +565,24 @@ impl Debug for MalType {
             Self::False => write!(f, "False"),
             Self::Nil => write!(f, "Nil"),
             Self::Number(arg0) => f.debug_tuple("Number").field(arg0).finish(),
+            Self::Char(arg0) => f.debug_tuple("Char").field(arg0).finish(),
             Self::Symbol(arg0) => f.debug_tuple("Symbol").field(arg0).finish(),
             Self::Keyword(arg0) => f.debug_tuple("Keyword").field(arg0).finish(),
             Self::String(arg0) => f.debug_tuple("String").field(arg0).finish(),
+            Self::Regex(arg0) => f.debug_tuple("Regex").field(arg0).finish(),
             Self::List(arg0, meta) => f.debug_tuple("List").field(arg0).field(meta).finish(),
             Self::Hashmap(arg0, meta) => f.debug_tuple("Hashmap").field(arg0).field(meta).finish(),
             Self::Vector(arg0, meta) => f.debug_tuple("Vector").field(arg0).field(meta).finish(),
-            Self::Function(_, meta) => f.debug_tuple("Function").field(meta).finish(),
+            Self::Set(arg0, meta) => f.debug_tuple("Set").field(arg0).field(meta).finish(),
+            Self::Function(_, name, meta) => {
+                f.debug_tuple("Function").field(name).field(meta).finish()
+            }
             Self::Closure(_, meta) => f.debug_tuple("Closure").field(meta).finish(),
             Self::Atom(arg0) => f.debug_tuple("Atom").field(arg0).finish(),
+            Self::LazySeq(_) => f.debug_tuple("LazySeq").finish(),
+            Self::Transducer(arg0) => f.debug_tuple("Transducer").field(arg0).finish(),
+            Self::Reduced(arg0) => f.debug_tuple("Reduced").field(arg0).finish(),
+            Self::Port(arg0) => f.debug_tuple("Port").field(arg0).finish(),
         }
     }
 }